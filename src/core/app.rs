@@ -1,3 +1,4 @@
+#[allow(deprecated)] // human_panic::setup_panic! still expands to `PanicInfo`
 pub fn start() -> anyhow::Result<()> {
     human_panic::setup_panic!();
 
@@ -10,17 +11,28 @@ pub fn start() -> anyhow::Result<()> {
         return Ok(());
     }
 
+    super::cli::Output::set_format(cli.global.output);
+
     let mut is_use_request_once_err = false;
+    let mut exit_code = 0;
     if let Some(command) = cli.command {
         match command {
+            super::cmd::Commands::Config(args) => super::config::exec(cli.global, args)?,
             super::cmd::Commands::Healthz => super::misc::exec(cli.global, "healthz")?,
             super::cmd::Commands::Info => super::misc::exec(cli.global, "info")?,
+            super::cmd::Commands::Outbox(args) => {
+                is_use_request_once_err = true;
+                super::outbox::exec(cli.global, args)?
+            }
             super::cmd::Commands::Ping => super::misc::exec(cli.global, "ping")?,
+            // request errors are warned, the exit code is the command's
+            super::cmd::Commands::Run(args) => exit_code = super::run::exec(cli.global, args)?,
+            super::cmd::Commands::Schedule(args) => super::schedule::exec(cli.global, args)?,
             super::cmd::Commands::Send(args) => {
                 is_use_request_once_err = true;
                 super::send::exec(cli.global, args)?
             }
-            super::cmd::Commands::Server => todo!(),
+            super::cmd::Commands::Server(args) => super::server::exec(cli.global, args)?,
         }
     }
 
@@ -29,5 +41,10 @@ pub fn start() -> anyhow::Result<()> {
         return Err(anyhow!("at least one request error occurred"));
     }
 
+    // the wrapped command of `run` decides
+    if exit_code != 0 {
+        std::process::exit(exit_code);
+    }
+
     Ok(())
 }
@@ -1,3 +1,4 @@
+use super::api::RequestPolicy;
 use clap::Parser;
 use colored::Colorize;
 use indicatif::{MultiProgress, ProgressBar, ProgressStyle};
@@ -46,6 +47,9 @@ impl Main {
 
     pub fn create_multi_progress(tasks_count: u64) -> anyhow::Result<(MultiProgress, ProgressBar)> {
         let pb_multi = MultiProgress::new();
+        if !Output::is_text() {
+            pb_multi.set_draw_target(indicatif::ProgressDrawTarget::hidden());
+        }
 
         let pb_main = pb_multi.add(ProgressBar::new(tasks_count));
         let pb_main_msg_width = match (tasks_count as f64).log10() as u32 + 1 {
@@ -68,14 +72,34 @@ impl Main {
         pb_task: ProgressBar,
         iname: (usize, String),
         req: reqwest::RequestBuilder,
-    ) -> anyhow::Result<()> {
+        policy: RequestPolicy,
+    ) -> anyhow::Result<RequestResult> {
+        let mut results =
+            Self::batch_handle(semaphore, pb_task, vec![iname], Vec::new(), req, policy).await?;
+        Ok(results.remove(0))
+    }
+
+    /// One request for all of `inames`, `keys` split the response of a `device_keys` one.
+    pub async fn batch_handle(
+        semaphore: Arc<Semaphore>,
+        pb_task: ProgressBar,
+        inames: Vec<(usize, String)>,
+        keys: Vec<String>,
+        req: reqwest::RequestBuilder,
+        policy: RequestPolicy,
+    ) -> anyhow::Result<Vec<RequestResult>> {
         let _permit = semaphore.acquire().await?;
 
         pb_task.set_style(ProgressStyle::with_template(
             "{spinner:.green} {prefix:30} {wide_msg} {elapsed:.yellow}",
         )?);
 
-        pb_task.set_prefix(format!("#{:<3} {}", iname.0, iname.1));
+        let (index, name) = inames[0].clone();
+        if inames.len() == 1 {
+            pb_task.set_prefix(format!("#{index:<3} {name}"));
+        } else {
+            pb_task.set_prefix(format!("#{index:<3} {name} +{}", inames.len() - 1));
+        }
         pb_task.set_message("Sending");
 
         // #[cfg(test)]
@@ -90,39 +114,91 @@ impl Main {
         //     pb_task.set_message("TStart");
         // }
 
-        tokio::select! {
+        let ret = tokio::select! {
             biased;
-            v = req.send() =>{
-                match v {
-                    Ok(resp)=>{
-                        let status = resp.status();
-                        let title = if status == 200 {
-                            "Success".bold().blue()
-                        } else {
-                            Main::set_request_once_err(true);
-                            "Failed".bold().red()
-                        };
-                        pb_task.set_message(format!("{:10} Status {status}", title));
-                    },
-                    Err(err)=>{
-                        // TODO collect iname and error
-                        // because the error message is too long
-                        Main::set_request_once_err(true);
-                        let title = "Error".bold().red();
-                        pb_task.set_message(format!("{:10} Status {err}", title));
-                    }
-                }
-            },
+            ret = Self::request_send((index, name), req, policy, &pb_task) => ret,
             _ = async{
                 loop{
                     pb_task.tick();
                     time::sleep(time::Duration::from_millis(50)).await;
                 }
-            } => {}
+            } => unreachable!(),
         };
 
+        let results = if inames.len() == 1 {
+            vec![ret]
+        } else {
+            ret.response
+                .split(&keys)
+                .into_iter()
+                .zip(inames)
+                .map(|(response, (index, device))| RequestResult {
+                    index,
+                    device,
+                    response,
+                })
+                .collect()
+        };
+
+        let failed = results
+            .iter()
+            .filter(|v| !v.is_success())
+            .collect::<Vec<_>>();
+        let title = match failed.first() {
+            None => "Success".bold().blue(),
+            Some(v) if v.error.is_some() => "Error".bold().red(),
+            Some(_) => "Failed".bold().red(),
+        };
+        let mut reason = failed.first().unwrap_or(&&results[0]).reason();
+        if results.len() > 1 {
+            reason = format!(
+                "{}/{} {reason}",
+                results.len() - failed.len(),
+                results.len()
+            );
+        }
+        // the full reason goes to the summary, the error message is too long
+        pb_task.set_message(format!(
+            "{:10} {}",
+            title,
+            crate::util::truncate_utf8(&reason, 60)
+        ));
+        if !failed.is_empty() {
+            Main::set_request_once_err(true);
+        }
+
         pb_task.finish();
-        Ok(())
+        for ret in results.iter() {
+            Output::ndjson(ret)?;
+        }
+        Ok(results)
+    }
+
+    /// Retries connection errors, 5xx and 429 by `policy`,
+    /// the retry is shown in `pb_task`.
+    pub async fn request_send(
+        iname: (usize, String),
+        req: reqwest::RequestBuilder,
+        policy: RequestPolicy,
+        pb_task: &ProgressBar,
+    ) -> RequestResult {
+        let response = super::api::Client::deliver(req, policy, |ret, backoff| {
+            pb_task.set_message(format!(
+                "{:10} {}/{} in {} {}",
+                "Retry".bold().yellow(),
+                ret.attempts,
+                policy.retries,
+                humantime::format_duration(backoff),
+                crate::util::truncate_utf8(&ret.reason(), 40)
+            ));
+        })
+        .await;
+
+        RequestResult {
+            index: iname.0,
+            device: iname.1,
+            response,
+        }
     }
 
     pub fn output_completions<S: clap_complete::Generator>(shell: S, command: &mut clap::Command) {
@@ -136,14 +212,113 @@ impl Main {
     }
 }
 
+/// A response of the library, named for the progress bar and `--output json`.
+#[derive(Debug, Default, serde::Serialize)]
+pub struct RequestResult {
+    pub index: usize,
+    pub device: String,
+    #[serde(flatten)]
+    pub response: super::api::Response,
+}
+
+impl std::ops::Deref for RequestResult {
+    type Target = super::api::Response;
+
+    fn deref(&self) -> &Self::Target {
+        &self.response
+    }
+}
+
+#[derive(Debug, Default, serde::Serialize)]
+pub struct RequestSummary {
+    pub success: usize,
+    pub failed: usize,
+    pub results: Vec<RequestResult>,
+}
+
+impl RequestSummary {
+    pub fn print(&self) {
+        println!(
+            "{}",
+            Output::exec_string(&format!(
+                "Summary {} success {} failed",
+                self.success, self.failed
+            ))
+        );
+
+        let mut failed = self
+            .results
+            .iter()
+            .filter(|v| !v.is_success())
+            .collect::<Vec<_>>();
+        failed.sort_by_key(|v| v.index);
+        for v in failed.into_iter() {
+            println!(
+                "{} {}",
+                format!("#{:<3} {:30}", v.index, v.device).bold().red(),
+                v.reason()
+            );
+        }
+    }
+
+    pub fn push(&mut self, ret: RequestResult) {
+        if ret.is_success() {
+            self.success += 1;
+        } else {
+            self.failed += 1;
+        }
+        self.results.push(ret);
+    }
+}
+
 pub struct Output;
 impl Output {
     // pub fn info() {
     //     todo!()
     // }
 
+    fn output_format<'a>() -> MutexGuard<'a, super::cmd::OutputFormat> {
+        lazy_static! {
+            pub static ref OUTPUT_FORMAT: Mutex<super::cmd::OutputFormat> =
+                Mutex::new(super::cmd::OutputFormat::Text);
+        }
+        OUTPUT_FORMAT.lock().unwrap()
+    }
+
+    pub fn set_format(format: super::cmd::OutputFormat) {
+        *Self::output_format() = format;
+    }
+
+    pub fn format() -> super::cmd::OutputFormat {
+        *Self::output_format()
+    }
+
+    pub fn is_text() -> bool {
+        Self::format() == super::cmd::OutputFormat::Text
+    }
+
+    /// Pretty with `json`, a single line with `ndjson`, nothing with `text`.
+    pub fn json<T: serde::Serialize>(v: &T) -> anyhow::Result<()> {
+        match *Self::output_format() {
+            super::cmd::OutputFormat::Text => {}
+            super::cmd::OutputFormat::Json => println!("{}", serde_json::to_string_pretty(v)?),
+            super::cmd::OutputFormat::Ndjson => println!("{}", serde_json::to_string(v)?),
+        }
+        Ok(())
+    }
+
+    /// Streamed as soon as it is done, only with `ndjson`.
+    pub fn ndjson<T: serde::Serialize>(v: &T) -> anyhow::Result<()> {
+        if *Self::output_format() == super::cmd::OutputFormat::Ndjson {
+            println!("{}", serde_json::to_string(v)?);
+        }
+        Ok(())
+    }
+
     pub fn exec(s: &str) {
-        println!("{}", Self::exec_string(s));
+        if Self::is_text() {
+            println!("{}", Self::exec_string(s));
+        }
     }
 
     pub fn exec_string(s: &str) -> String {
@@ -159,77 +334,53 @@ impl Output {
 mod tests {
     use super::*;
     use crate::util::tests::*;
-    use tokio::{runtime::Runtime, task};
-
-    // #[test]
-    fn dump_help() {
-        let cli = Main::parse_from(["", "--help"]);
-        dbg!(&cli);
-    }
-
-    // #[test]
-    fn test_multi_request() -> anyhow::Result<()> {
-        let client = reqwest::Client::builder().build()?;
-
-        let create_request_tasks = |count: usize| {
-            let mut vec = Vec::with_capacity(count);
-            vec.push(client.post("https://httpbin.org/status/301"));
-            vec.push(client.post("https://httpbin.org/status/200"));
-            vec.push(client.post("https://httpbin.org/status/302"));
-            vec.push(client.post("https://httpbin.org/status/404"));
-            vec.push(client.post("https://httpbin.org/status/500"));
-            let count = count - 5;
-
-            for index in 0..count {
-                let status_code = random_number(200, 500);
-                let url = format!("https://httpbin.org/status/{status_code}");
-                vec.push(client.post(url));
-            }
-            vec
-        };
 
-        let vec = create_request_tasks(6);
-        // dbg!(&vec);
-        // crate::println_dash!(50);
-        ////////////////////////////////////////////////
-        let (pb_multi, pb_main) = Main::create_multi_progress(vec.len() as u64)?;
-        let limit_conn = 5;
-        let semaphore = Arc::new(Semaphore::new(limit_conn));
-        pb_multi.println(Output::exec_string(&format!("Test -l {}", limit_conn)))?;
-
-        let mut join_set = task::JoinSet::new();
-        Runtime::new()?.block_on(async {
-            let ret: anyhow::Result<_> = Ok(());
-
-            for (index, req) in vec.into_iter().enumerate() {
-                let name = crate::util::hash_hex_string(index);
-                let pb_task = pb_multi.insert_before(&pb_main, ProgressBar::new(1));
-
-                join_set.spawn(Main::request_handle(
-                    semaphore.clone(),
-                    pb_task,
-                    (index, name),
-                    req,
-                ));
-            }
-
-            while let Some(v) = join_set.join_next().await {
-                match v {
-                    Ok(res) => {
-                        res?;
-                        pb_main.inc(1);
-                    }
-                    Err(err) => {
-                        return Err(anyhow::anyhow!(err));
-                    }
-                }
-            }
-
-            pb_main.finish_with_message("All tasks done");
-            println!("\n");
-            // Main::warn_request_once_err();
+    #[test]
+    fn test_request_summary() -> anyhow::Result<()> {
+        let mut summary = RequestSummary::default();
+        summary.push(RequestResult {
+            index: 0,
+            device: random_string(10),
+            response: super::super::api::Response {
+                status: Some(200),
+                code: Some(200),
+                message: Some("success".into()),
+                ..Default::default()
+            },
+        });
+        summary.push(RequestResult {
+            index: 1,
+            device: random_string(10),
+            response: super::super::api::Response {
+                status: Some(400),
+                code: Some(400),
+                message: Some("failed to get device token".into()),
+                ..Default::default()
+            },
+        });
+        summary.push(RequestResult {
+            index: 2,
+            device: random_string(10),
+            response: super::super::api::Response {
+                error: Some("error sending request".into()),
+                ..Default::default()
+            },
+        });
+
+        summary.push(RequestResult {
+            index: 3,
+            device: random_string(10),
+            response: super::super::api::Response {
+                status: Some(200),
+                code: Some(400),
+                message: Some("failed to get device token".into()),
+                ..Default::default()
+            },
+        });
 
-            ret
-        })
+        assert_eq!((summary.success, summary.failed), (1, 3));
+        summary.print();
+        println!("{}", serde_json::to_string_pretty(&summary)?);
+        Ok(())
     }
 }
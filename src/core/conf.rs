@@ -1,5 +1,5 @@
 use anyhow::Context;
-use config::{builder::DefaultState, Config, ConfigBuilder, File, FileFormat};
+use config::{builder::DefaultState, Config, ConfigBuilder, Environment, File, FileFormat};
 use lazy_static::lazy_static;
 use std::{
     borrow::Cow,
@@ -8,19 +8,39 @@ use std::{
 
 pub type SyncBuilder = ConfigBuilder<DefaultState>;
 
+// also used by the library without the command line
+pub use super::api::{
+    fallback_request_style, fallback_retries, fallback_retry_backoff, fallback_timeout,
+    fallback_user_agent,
+};
+
+#[inline]
+pub fn fallback_body_limit() -> usize {
+    3072
+}
+
 #[inline]
 pub fn fallback_limit_conn() -> u16 {
     10
 }
 
 #[inline]
-pub fn fallback_remote<'a>() -> &'a str {
-    "https://api.day.app"
+pub fn fallback_listen<'a>() -> &'a str {
+    "127.0.0.1:8080"
+}
+
+/// `outbox` under the user data dir.
+pub fn fallback_outbox_dir() -> String {
+    directories::ProjectDirs::from("", "", crate::named!())
+        .map(|v| v.data_dir().join("outbox"))
+        .unwrap_or_else(|| std::env::temp_dir().join(crate::named!()).join("outbox"))
+        .to_string_lossy()
+        .into_owned()
 }
 
 #[inline]
-pub fn fallback_user_agent<'a>() -> &'a str {
-    crate::user_agent!()
+pub fn fallback_remote<'a>() -> &'a str {
+    "https://api.day.app"
 }
 
 #[derive(Default, serde::Deserialize)]
@@ -32,9 +52,26 @@ pub struct Common<'a> {
     #[serde(borrow)]
     pub user_agent: Cow<'a, str>,
 
+    #[serde(borrow)]
+    pub proxy: Option<Cow<'a, str>>,
+
+    pub ca_file: Option<PathBuf>,
+
+    pub client_cert: Option<PathBuf>,
+
+    pub client_key: Option<PathBuf>,
+
+    pub insecure_skip_verify: bool,
+
+    #[serde(borrow)]
+    pub connect_timeout: Option<Cow<'a, str>>,
+
     #[serde(skip)]
     pub _config: FileDisplay,
 
+    #[serde(skip)]
+    pub _profile: Option<String>,
+
     #[serde(skip)]
     pub _dump_hide: bool,
 }
@@ -47,6 +84,8 @@ impl<'a> std::fmt::Debug for Common<'a> {
 
         f.field("remote", &self.remote);
         f.field("user_agent", &self.user_agent);
+        self.debug_http(&mut f);
+        f.field("profile", &self._profile);
 
         if self._dump_hide {
             f.field("_config", &self._config);
@@ -63,6 +102,71 @@ impl<'a> Common<'a> {
             .set_default("user_agent", fallback_user_agent())?)
     }
 
+    /// Connection flags above everything else.
+    pub fn set_override_http(
+        builder: SyncBuilder,
+        connection: super::cmd::ConnectionOptions,
+    ) -> anyhow::Result<SyncBuilder> {
+        let path = |v: Option<PathBuf>| v.map(|v| v.to_string_lossy().into_owned());
+        Ok(builder
+            .set_override_option("proxy", connection.proxy)?
+            .set_override_option("ca_file", path(connection.ca_file))?
+            .set_override_option("client_cert", path(connection.client_cert))?
+            .set_override_option("client_key", path(connection.client_key))?
+            .set_override_option(
+                "insecure_skip_verify",
+                connection.insecure_skip_verify.then_some(true),
+            )?
+            .set_override_option("connect_timeout", connection.connect_timeout)?)
+    }
+
+    /// Resolve secrets in the remote, then verify it and the connection.
+    pub fn verify(&mut self, secrets: &mut super::secret::Secrets) -> anyhow::Result<()> {
+        self.verify_remote(secrets)?;
+        self.verify_http(secrets)
+    }
+
+    pub fn verify_remote(&mut self, secrets: &mut super::secret::Secrets) -> anyhow::Result<()> {
+        self.remote = secrets.resolve(&self.remote)?.into();
+        super::bark::Remote::verify(&self.remote)
+    }
+
+    /// Resolve secrets in the proxy, then read the TLS files.
+    pub fn verify_http(&mut self, secrets: &mut super::secret::Secrets) -> anyhow::Result<()> {
+        if let Some(proxy) = &self.proxy {
+            self.proxy = Some(secrets.resolve(proxy)?.into());
+        }
+        self.http_options()?.build()?;
+        Ok(())
+    }
+
+    pub fn http_options(&self) -> anyhow::Result<super::api::HttpOptions> {
+        Ok(super::api::HttpOptions {
+            user_agent: self.user_agent.to_string(),
+            proxy: self.proxy.as_deref().map(String::from),
+            ca_file: self.ca_file.clone(),
+            client_cert: self.client_cert.clone(),
+            client_key: self.client_key.clone(),
+            insecure_skip_verify: self.insecure_skip_verify,
+            connect_timeout: self
+                .connect_timeout
+                .as_deref()
+                .map(|v| super::api::RequestPolicy::parse_duration("connect_timeout", v))
+                .transpose()?,
+        })
+    }
+
+    /// Flattened into the dump of each subcommand, the proxy password masked.
+    pub fn debug_http(&self, f: &mut std::fmt::DebugStruct<'_, '_>) {
+        let proxy = self.proxy.as_deref().map(super::bark::Remote::mask);
+        f.field("proxy", &proxy);
+        f.field("ca_file", &self.ca_file);
+        f.field("client_cert", &self.client_cert);
+        f.field("client_key", &self.client_key);
+        f.field("insecure_skip_verify", &self.insecure_skip_verify);
+        f.field("connect_timeout", &self.connect_timeout);
+    }
+
     pub fn dump(mut self) -> anyhow::Result<()> {
         self.remote = super::bark::Remote::dump(&self.remote)?.into();
         println!("{:#?}", self);
@@ -75,16 +179,19 @@ impl<'a> Common<'a> {
         } else {
             super::conf::FileBuilder::from_cmd_global_options(global.config_file_paths)?
         };
+        fb = fb.with_profile(global.profile)?;
         fb.builder = Self::builder_default(fb.builder)?
             .set_override_option("remote", global.remote)?
             .set_override_option("user_agent", global.user_agent)?;
+        fb.builder = Self::set_override_http(fb.builder, global.connection)?;
 
         let mut _self: Self = fb.builder.build()?.try_deserialize()?;
         _self._config = FileDisplay::new(fb.sources);
+        _self._profile = fb.profile;
         _self._dump_hide = global.dump_level >= 2;
 
         // real
-        super::bark::Remote::verify(&_self.remote)?;
+        _self.verify(&mut super::secret::Secrets::default())?;
 
         Ok(_self)
     }
@@ -193,6 +300,19 @@ impl FileSource {
         ))
     }
 
+    /// Existing files of this source, `preset.<ext>` probed for presets.
+    pub fn files(&self) -> Vec<PathBuf> {
+        let p = Path::new(&self.abs);
+        if self._is_input {
+            return vec![p.to_path_buf()];
+        }
+        ["ini", "json", "json5", "toml", "yaml", "yml"]
+            .iter()
+            .map(|ext| p.with_extension(ext))
+            .filter(|v| v.is_file())
+            .collect()
+    }
+
     pub fn preset_apps() -> anyhow::Result<Vec<Self>> {
         Ok(vec![
             Self::preset_etc(crate::named!(), false)?,
@@ -206,6 +326,98 @@ impl FileSource {
 pub struct FileBuilder {
     pub builder: SyncBuilder,
     pub sources: Vec<FileSource>,
+    pub profile: Option<String>,
+}
+
+/// `profiles.<name>` of the files, as a source of its own.
+#[derive(Clone, Debug)]
+struct ProfileSource(config::Map<String, config::Value>);
+
+impl config::Source for ProfileSource {
+    fn clone_into_box(&self) -> Box<dyn config::Source + Send + Sync> {
+        Box::new(self.clone())
+    }
+
+    fn collect(&self) -> Result<config::Map<String, config::Value>, config::ConfigError> {
+        Ok(self.0.clone())
+    }
+}
+
+/// A source with its context aliases renamed to the full keys, so that an alias
+/// overrides the full key of an earlier source, both in one source are rejected.
+#[derive(Clone, Debug)]
+struct Normalized<S>(S);
+
+impl<S> config::Source for Normalized<S>
+where
+    S: config::Source + Clone + Send + Sync + 'static,
+{
+    fn clone_into_box(&self) -> Box<dyn config::Source + Send + Sync> {
+        Box::new(self.clone())
+    }
+
+    fn collect(&self) -> Result<config::Map<String, config::Value>, config::ConfigError> {
+        Self::normalize(&[], self.0.collect()?)
+            .map_err(|err| config::ConfigError::Message(format!("{err:#}")))
+    }
+}
+
+impl<S> Normalized<S> {
+    /// Tables of contexts, also under `profiles.<name>`.
+    fn is_contexts(path: &[&str]) -> bool {
+        let path = match path {
+            ["profiles", _, rest @ ..] => rest,
+            _ => path,
+        };
+        matches!(
+            path,
+            ["contexts"]
+                | ["devices", _, "contexts"]
+                | ["templates", _]
+                | ["run", "success" | "failure"]
+                | ["schedules", _, "contexts"]
+        )
+    }
+
+    /// Keys of the environment are dotted paths, keys of files are nested tables.
+    fn normalize(
+        path: &[&str],
+        map: config::Map<String, config::Value>,
+    ) -> anyhow::Result<config::Map<String, config::Value>> {
+        let mut fields = std::collections::HashMap::new();
+        let mut normalized = config::Map::with_capacity(map.len());
+        for (k, mut v) in map.into_iter() {
+            let mut full = path.to_vec();
+            full.extend(k.split('.'));
+            let (last, parent) = full.split_last().unwrap();
+
+            let mut key = k.clone();
+            if Self::is_contexts(parent) {
+                // unsupported keys fail later with the value
+                if let Ok(Some(field)) = super::api::Notification::field_of(last) {
+                    let dotted = |v: &str| [parent, &[v]].concat().join(".");
+                    if let Some(prev) = fields.insert((parent.join("."), field), dotted(last)) {
+                        let mut pair = [prev, dotted(last)];
+                        pair.sort();
+                        return Err(anyhow::anyhow!(
+                            "bark_context `{}` conflicts with `{}`",
+                            pair[0],
+                            pair[1]
+                        ));
+                    }
+                    key = match k.rsplit_once('.') {
+                        Some((prefix, _)) => format!("{prefix}.{field}"),
+                        None => field.to_string(),
+                    };
+                }
+            }
+            if let config::ValueKind::Table(table) = &mut v.kind {
+                *table = Self::normalize(&full, std::mem::take(table))?;
+            }
+            normalized.insert(key, v);
+        }
+        Ok(normalized)
+    }
 }
 
 impl FileBuilder {
@@ -216,6 +428,8 @@ impl FileBuilder {
 
         let mut is_lazy_using = IS_LAZY_USING.lock().unwrap();
         if *is_lazy_using {
+            // long-running commands merge the config more than once
+            #[cfg(debug_assertions)]
             super::cli::Output::warn("is_lazy_using, skip");
             return;
         }
@@ -289,13 +503,62 @@ impl FileBuilder {
         *FILE_FORMAT_LEN
     }
 
+    #[inline]
+    pub fn env_prefix() -> String {
+        crate::named!().to_uppercase()
+    }
+
+    /// `IBARK_REMOTE`, `IBARK_DEVICES__PHONE`, between files and flags.
+    ///
+    /// Keys are lowercased by the config crate.
+    pub fn env_source() -> Environment {
+        Self::env_source_of(std::env::vars())
+    }
+
+    fn env_source_of(vars: impl Iterator<Item = (String, String)>) -> Environment {
+        Environment::with_prefix(&Self::env_prefix())
+            .prefix_separator("_")
+            .separator("__")
+            .ignore_empty(true)
+            .source(Some(vars.filter(|(k, _)| !Self::is_cli_env(k)).collect()))
+    }
+
+    /// Variables of flags, e.g. `IBARK_PROFILE`, clap reads them, they are no config keys.
+    pub fn is_cli_env(k: &str) -> bool {
+        fn walk(cmd: &clap::Command, vars: &mut Vec<String>) {
+            vars.extend(
+                cmd.get_arguments()
+                    .filter_map(|v| v.get_env())
+                    .map(|v| v.to_string_lossy().into_owned()),
+            );
+            for sub in cmd.get_subcommands() {
+                walk(sub, vars);
+            }
+        }
+        lazy_static! {
+            static ref CLI_ENV: Vec<String> = {
+                let mut vars = Vec::new();
+                walk(
+                    &<super::cli::Main as clap::CommandFactory>::command(),
+                    &mut vars,
+                );
+                vars
+            };
+        }
+        CLI_ENV.iter().any(|v| v.eq_ignore_ascii_case(k))
+    }
+
     pub fn with_input(sources: Vec<FileSource>) -> anyhow::Result<Self> {
         let mut builder = Config::builder();
         for s in sources.iter() {
-            builder = builder.add_source(File::with_name(&s.abs).required(s.required));
+            builder = builder.add_source(Normalized(File::with_name(&s.abs).required(s.required)));
         }
 
-        Ok(Self { builder, sources })
+        Ok(Self {
+            builder,
+            sources,
+            profile: None,
+        })
     }
 
     pub fn with_preset() -> anyhow::Result<Self> {
@@ -306,10 +569,32 @@ impl FileBuilder {
         let ffmt = FileBuilder::seq_file_format();
         for s in sources.iter() {
             for f in ffmt.iter() {
-                builder = builder.add_source(File::new(&s.abs, *f).required(s.required));
+                builder =
+                    builder.add_source(Normalized(File::new(&s.abs, *f).required(s.required)));
             }
         }
-        Ok(Self { builder, sources })
+        Ok(Self {
+            builder,
+            sources,
+            profile: None,
+        })
+    }
+
+    /// files < `profiles.<name>` < `IBARK_*`, flags are overrides on top.
+    pub fn with_profile(mut self, profile: Option<String>) -> anyhow::Result<Self> {
+        if let Some(name) = profile {
+            let table = self
+                .builder
+                .clone()
+                .build()?
+                .get_table(&format!("profiles.{name}"))
+                .with_context(|| format!("config_profile `{name}` not found"))?;
+            self.builder = self.builder.add_source(ProfileSource(table));
+            self.profile = Some(name);
+        }
+        self.builder = self.builder.add_source(Normalized(Self::env_source()));
+
+        Ok(self)
     }
 
     pub fn from_cmd_global_options(paths: Vec<PathBuf>) -> anyhow::Result<Self> {
@@ -332,6 +617,8 @@ impl FileBuilder {
 #[derive(Default)]
 pub struct FileDisplay {
     pub sources: Vec<FileSource>,
+    /// names only, values may be secrets
+    pub env: Vec<String>,
 }
 
 impl std::fmt::Debug for FileDisplay {
@@ -342,13 +629,25 @@ impl std::fmt::Debug for FileDisplay {
                 &format_args!("{:?}", FileBuilder::seq_file_format()),
             )
             .field("sources", &self.sources)
+            .field("env", &self.env)
             .finish()
     }
 }
 
 impl FileDisplay {
     pub fn new(sources: Vec<FileSource>) -> Self {
-        let mut _self = Self { sources };
+        let prefix = format!("{}_", FileBuilder::env_prefix());
+        let mut env = std::env::vars()
+            .filter(|(k, v)| {
+                k.to_uppercase().starts_with(&prefix)
+                    && !v.is_empty()
+                    && !FileBuilder::is_cli_env(k)
+            })
+            .map(|(k, _)| k)
+            .collect::<Vec<_>>();
+        env.sort();
+
+        let mut _self = Self { sources, env };
         FileBuilder::lazy_using(&mut _self.sources);
         _self
     }
@@ -356,7 +655,6 @@ impl FileDisplay {
 
 #[cfg(test)]
 mod tests {
-    use super::super::*;
     use super::*;
     use crate::util;
 
@@ -386,4 +684,23 @@ mod tests {
 
         Ok(())
     }
+
+    #[test]
+    fn test_env_source() -> anyhow::Result<()> {
+        use ::config::Source;
+
+        assert!(FileBuilder::is_cli_env("IBARK_PROFILE"));
+        // set here, not in the process, clap of other tests would read it
+        let vars = [
+            ("IBARK_PROFILE", "staging"),
+            ("IBARK_REMOTE", "https://hello.world"),
+            ("IBARK_DEVICES__PHONE", "d://key"),
+        ]
+        .map(|(k, v)| (k.to_string(), v.to_string()));
+        let map = FileBuilder::env_source_of(vars.into_iter()).collect()?;
+        let mut keys = map.keys().collect::<Vec<_>>();
+        keys.sort();
+        assert_eq!(keys, ["devices.phone", "remote"]);
+        Ok(())
+    }
 }
@@ -1,19 +1,22 @@
+use std::time::Instant;
 use tokio::runtime::Runtime;
 
 #[allow(dead_code)]
-#[derive(Debug, serde::Deserialize)]
+#[derive(Debug, serde::Deserialize, serde::Serialize)]
 struct InfoResponse<'a> {
     #[serde(borrow)]
     version: &'a str,
 }
 
-#[allow(dead_code)]
-#[derive(Debug, serde::Deserialize)]
-struct PingResponse<'a> {
-    code: u16,
-    #[serde(borrow)]
-    message: &'a str,
-    timestamp: u64,
+/// Emitted with `--output json|ndjson`.
+#[derive(Debug, serde::Serialize)]
+struct MiscOutput {
+    name: String,
+    remote: String,
+    status: u16,
+    latency_ms: u64,
+    /// parsed if possible, raw text otherwise
+    response: serde_json::Value,
 }
 
 pub fn exec(global: super::cmd::GlobalOptions, name: &str) -> anyhow::Result<()> {
@@ -37,36 +40,63 @@ pub fn exec(global: super::cmd::GlobalOptions, name: &str) -> anyhow::Result<()>
             super::bark::Remote::scheme_host_port(&conf.remote)?
         ));
 
-        let req = reqwest::Client::builder()
-            .user_agent(conf.user_agent.as_ref())
+        let req = conf
+            .http_options()?
             .build()?
             .get(format!("{}/{name}", conf.remote.as_ref()));
 
-        let resp = req.send().await?.text().await?;
+        let start = Instant::now();
+        let resp = req.send().await?;
+        let status = resp.status().as_u16();
+        let resp = resp.text().await?;
+        let latency_ms = start.elapsed().as_millis() as u64;
 
-        match name {
-            "healthz" => {
-                println!("{}", resp);
-            }
-            "info" => match serde_json::from_str::<InfoResponse>(&resp) {
-                Ok(v) => {
-                    println!("{:#?}", v);
-                }
-                Err(_) => {
-                    println!("{}", resp);
-                }
-            },
-            "ping" => match serde_json::from_str::<PingResponse>(&resp) {
-                Ok(v) => {
-                    println!("{:#?}", v);
-                }
-                Err(_) => {
+        if super::cli::Output::is_text() {
+            match name {
+                "healthz" => {
                     println!("{}", resp);
                 }
-            },
-            _ => unreachable!(),
+                "info" => match serde_json::from_str::<InfoResponse>(&resp) {
+                    Ok(v) => {
+                        println!("{:#?}", v);
+                    }
+                    Err(_) => {
+                        println!("{}", resp);
+                    }
+                },
+                "ping" => match serde_json::from_str::<super::bark::Response>(&resp) {
+                    Ok(v) => {
+                        println!("{:#?}", v);
+                    }
+                    Err(_) => {
+                        println!("{}", resp);
+                    }
+                },
+                _ => unreachable!(),
+            }
+            return ret;
         }
 
+        let response = match name {
+            "info" => serde_json::from_str::<InfoResponse>(&resp)
+                .ok()
+                .map(serde_json::to_value)
+                .transpose()?,
+            "ping" => serde_json::from_str::<super::bark::Response>(&resp)
+                .ok()
+                .map(serde_json::to_value)
+                .transpose()?,
+            _ => None,
+        };
+
+        super::cli::Output::json(&MiscOutput {
+            name: name.to_string(),
+            remote: super::bark::Remote::scheme_host_port(&conf.remote)?,
+            status,
+            latency_ms,
+            response: response.unwrap_or_else(|| resp.trim_end().into()),
+        })?;
+
         ret
     })
 }
@@ -74,15 +104,8 @@ pub fn exec(global: super::cmd::GlobalOptions, name: &str) -> anyhow::Result<()>
 #[cfg(test)]
 mod tests {
     use super::super::*;
-    use super::*;
     use clap::Parser;
 
-    // #[test]
-    fn dump_help() {
-        // healthz info ping
-        let cli = cli::Main::parse_from(["", "healthz", "--help"]);
-    }
-
     #[test]
     fn test_dump() -> anyhow::Result<()> {
         let miscs = ["healthz", "info", "ping"];
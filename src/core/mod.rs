@@ -1,8 +1,29 @@
+#[cfg(feature = "cli")]
 pub(crate) mod app;
 
+pub(crate) mod api;
 mod bark;
+#[cfg(feature = "cli")]
 mod cli;
+#[cfg(feature = "cli")]
 mod cmd;
+#[cfg(feature = "cli")]
 mod conf;
+#[cfg(feature = "cli")]
+mod config;
+#[cfg(feature = "cli")]
 mod misc;
+#[cfg(feature = "cli")]
+mod outbox;
+#[cfg(feature = "cli")]
+mod run;
+#[cfg(feature = "cli")]
+mod schedule;
+#[cfg(feature = "cli")]
+mod secret;
+#[cfg(feature = "cli")]
 mod send;
+#[cfg(feature = "cli")]
+mod server;
+#[cfg(feature = "cli")]
+mod template;
@@ -0,0 +1,351 @@
+use anyhow::{anyhow, Context};
+use std::{
+    collections::VecDeque,
+    io::{BufRead, BufReader, Read, Write},
+    process::{Command, ExitStatus, Stdio},
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+#[derive(clap::Args, Debug)]
+pub struct RunArgs {
+    /// Specify notification contexts, override `run.success` and `run.failure`
+    #[arg(
+        required = false,
+        short,
+        long,
+        value_name = "KEYVAL",
+        value_hint = clap::ValueHint::Other,
+        value_parser = super::cmd::parse_key_val::<String,String>,
+    )]
+    pub contexts: Vec<(String, String)>,
+
+    /// Device name, @group or tag:name from the config file or your full input
+    #[arg(
+        required = true,
+        short,
+        long = "device",
+        value_name = "DEVICE",
+        value_hint = clap::ValueHint::Other,
+    )]
+    pub devices: Vec<String>,
+
+    #[arg(
+        short = 'l',
+        long,
+        value_name = "LIMIT",
+        help = format!("Specify max concurrent tasks per remote [fallback: {}]", super::conf::fallback_limit_conn())
+    )]
+    pub limit_conn: Option<u16>,
+
+    /// Save failed notifications to the outbox, see `ibark outbox`
+    #[arg(long)]
+    pub outbox: bool,
+
+    /// Append last N lines of stdout and stderr to the body
+    #[arg(short = 'n', long, value_name = "N", default_value_t = 0)]
+    pub tail: usize,
+
+    /// Command to run
+    #[arg(
+        required = true,
+        last = true,
+        value_name = "COMMAND",
+        value_hint = clap::ValueHint::CommandWithArguments
+    )]
+    pub command: Vec<String>,
+}
+
+#[derive(Debug, Default, serde::Deserialize)]
+#[serde(default)]
+pub struct RunContexts {
+    pub success: super::api::Notification,
+    pub failure: super::api::Notification,
+}
+
+#[derive(Default, serde::Deserialize)]
+#[serde(default)]
+pub struct RunConf<'a> {
+    #[serde(borrow, flatten)]
+    pub common: super::conf::Common<'a>,
+
+    pub body_limit: usize,
+    pub run: RunContexts,
+}
+
+impl<'a> std::fmt::Debug for RunConf<'a> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let mut f: std::fmt::DebugStruct<'_, '_> =
+            f.debug_struct(std::any::type_name::<Self>().split("::").last().unwrap());
+
+        // flatten
+        f.field("remote", &self.common.remote);
+        f.field("user_agent", &self.common.user_agent);
+        self.common.debug_http(&mut f);
+        f.field("profile", &self.common._profile);
+
+        f.field("body_limit", &self.body_limit);
+        f.field("run", &self.run);
+
+        if self.common._dump_hide {
+            f.field("_config", &self.common._config);
+        }
+
+        f.finish()
+    }
+}
+
+impl<'a> RunConf<'a> {
+    pub fn dump(mut self) -> anyhow::Result<()> {
+        self.common.remote = super::bark::Remote::dump(&self.common.remote)?.into();
+        println!("{:#?}", self);
+        Ok(())
+    }
+
+    pub fn from_cmd(global: super::cmd::GlobalOptions) -> anyhow::Result<Self> {
+        let mut fb = if global.config_file_paths.is_empty() {
+            super::conf::FileBuilder::with_preset()?
+        } else {
+            super::conf::FileBuilder::from_cmd_global_options(global.config_file_paths)?
+        };
+        fb = fb.with_profile(global.profile)?;
+
+        fb.builder = super::conf::Common::builder_default(fb.builder)?
+            .set_default("body_limit", super::conf::fallback_body_limit() as u64)?
+            .set_override_option("remote", global.remote)?
+            .set_override_option("user_agent", global.user_agent)?;
+        fb.builder = super::conf::Common::set_override_http(fb.builder, global.connection)?;
+
+        let mut _self: Self = fb.builder.build()?.try_deserialize()?;
+        _self.common._config = super::conf::FileDisplay::new(fb.sources);
+        _self.common._profile = fb.profile;
+        _self.common._dump_hide = global.dump_level >= 2;
+
+        // real
+        _self
+            .common
+            .verify(&mut super::secret::Secrets::default())?;
+
+        Ok(_self)
+    }
+}
+
+struct Finished {
+    status: ExitStatus,
+    elapsed: Duration,
+    tail: Vec<String>,
+}
+
+impl Finished {
+    fn spawn(command: &[String], tail: usize) -> anyhow::Result<Self> {
+        let (program, args) = command.split_first().unwrap();
+        let mut cmd = Command::new(program);
+        cmd.args(args);
+
+        let start = Instant::now();
+        let lines = Arc::new(Mutex::new(VecDeque::with_capacity(tail)));
+
+        let status = if tail == 0 && super::cli::Output::is_text() {
+            // keep the terminal as is
+            cmd.status()
+        } else {
+            let mut child = cmd
+                .stdout(Stdio::piped())
+                .stderr(Stdio::piped())
+                .spawn()
+                .with_context(|| format!("spawn `{program}` failed"))?;
+
+            // stdout is reserved for `--output json|ndjson`
+            let stdout: Box<dyn Write + Send> = if super::cli::Output::is_text() {
+                Box::new(std::io::stdout())
+            } else {
+                Box::new(std::io::stderr())
+            };
+
+            let tee = [
+                Self::tee(child.stdout.take().unwrap(), stdout, &lines, tail),
+                Self::tee(
+                    child.stderr.take().unwrap(),
+                    std::io::stderr(),
+                    &lines,
+                    tail,
+                ),
+            ];
+            let status = child.wait();
+            for t in tee.into_iter() {
+                t.join().ok();
+            }
+            status
+        }
+        .with_context(|| format!("run `{program}` failed"))?;
+
+        let tail = lines.lock().unwrap().drain(..).collect();
+        Ok(Self {
+            status,
+            elapsed: start.elapsed(),
+            tail,
+        })
+    }
+
+    fn tee<R, W>(
+        r: R,
+        mut w: W,
+        lines: &Arc<Mutex<VecDeque<String>>>,
+        tail: usize,
+    ) -> std::thread::JoinHandle<()>
+    where
+        R: Read + Send + 'static,
+        W: Write + Send + 'static,
+    {
+        let lines = lines.clone();
+        std::thread::spawn(move || {
+            for line in BufReader::new(r).split(b'\n') {
+                let Ok(line) = line else { break };
+                w.write_all(&line).ok();
+                w.write_all(b"\n").ok();
+                if tail == 0 {
+                    continue;
+                }
+
+                let mut lines = lines.lock().unwrap();
+                if lines.len() == tail {
+                    lines.pop_front();
+                }
+                lines.push_back(String::from_utf8_lossy(&line).trim_end().to_string());
+            }
+        })
+    }
+
+    fn contexts(&self, command: &[String]) -> super::api::Notification {
+        let title = if self.status.success() {
+            format!("`{}` succeeded", command.join(" "))
+        } else {
+            format!("`{}` failed", command.join(" "))
+        };
+        // milliseconds are noise for long builds
+        let elapsed = Duration::from_secs(self.elapsed.as_secs());
+
+        super::api::Notification::new()
+            .title(crate::util::truncate_utf8(&title, 128))
+            .body(format!(
+                "{}\nduration: {}",
+                self.status,
+                humantime::format_duration(elapsed)
+            ))
+    }
+
+    /// The tail is cut from the front to fit `limit`, the last lines matter most.
+    fn body(&self, contexts: &super::api::Notification, limit: usize) -> String {
+        let mut body = contexts.body.clone().unwrap_or_default();
+        if !self.tail.is_empty() {
+            body.push_str("\n\n");
+            let tail = self.tail.join("\n");
+            let left = limit.saturating_sub(body.len());
+            body.push_str(&crate::util::truncate_utf8_start(&tail, left));
+        }
+        body
+    }
+}
+
+/// Returns the exit code of the command.
+pub fn exec(global: super::cmd::GlobalOptions, args: RunArgs) -> anyhow::Result<i32> {
+    let dump_level = global.dump_level;
+    let conf = RunConf::from_cmd(global.clone())?;
+    if dump_level > 0 {
+        conf.dump()?;
+        return Ok(0);
+    }
+
+    let contexts = super::api::Notification::from_pairs(args.contexts)?;
+
+    super::cli::Output::exec(&format!("Run {}", args.command.join(" ")));
+    let finished = Finished::spawn(&args.command, args.tail)?;
+
+    // generated < run.success|run.failure < -c
+    let mut merge = finished.contexts(&args.command);
+    merge.merge(if finished.status.success() {
+        &conf.run.success
+    } else {
+        &conf.run.failure
+    });
+    merge.merge(&contexts);
+    let body = finished.body(&merge, conf.body_limit);
+    merge.body = None;
+
+    // best effort, the exit code of the command matters more
+    let ret = super::send::exec(
+        global,
+        super::send::SendArgs {
+            contexts: Vec::new(),
+            body: Some(body).filter(|v| !v.is_empty()),
+            body_file: None,
+            body_limit: None,
+            devices: args.devices,
+            exclude: Vec::new(),
+            limit_conn: args.limit_conn,
+            outbox: args.outbox,
+            batch: false,
+            template: None,
+            vars: Vec::new(),
+            at: None,
+            delay: None,
+            notification: Box::new(merge),
+        },
+    );
+    if let Err(err) = ret {
+        super::cli::Output::warn(&format!("notify failed: {err:#}"));
+    }
+    if super::cli::Main::is_request_once_err() {
+        super::cli::Output::warn("notify failed: at least one request error occurred");
+    }
+
+    match finished.status.code() {
+        Some(code) => Ok(code),
+        None => Err(anyhow!("`{}` {}", args.command.join(" "), finished.status)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::*;
+    use super::*;
+    use clap::Parser;
+
+    #[test]
+    fn test_dump() -> anyhow::Result<()> {
+        let cli = cli::Main::parse_from(["", "run", "-d", "phone", "-DD", "--", "false"]);
+        match cli.command.unwrap() {
+            cmd::Commands::Run(args) => assert_eq!(exec(cli.global, args)?, 0),
+            _ => unreachable!(),
+        }
+        Ok(())
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_finished() -> anyhow::Result<()> {
+        let command = ["sh", "-c", "seq 1 5; echo err >&2; exit 3"].map(String::from);
+        let finished = Finished::spawn(&command, 2)?;
+        assert_eq!(finished.status.code(), Some(3));
+        assert_eq!(finished.tail.len(), 2);
+
+        let contexts = dbg!(finished.contexts(&command));
+        dbg!(finished.body(&contexts, 3072));
+
+        // the last lines survive a small limit
+        let command = ["sh", "-c", "seq 1 100"].map(String::from);
+        let finished = Finished::spawn(&command, 50)?;
+        let contexts = finished.contexts(&command);
+        let limit = contexts.body.as_ref().unwrap().len() + 2 + 20;
+        let body = dbg!(finished.body(&contexts, limit));
+        assert_eq!(body.len(), limit);
+        assert!(body.ends_with("\n99\n100"));
+
+        let finished = Finished::spawn(&["true".to_string()], 0)?;
+        assert!(finished.status.success());
+        assert!(finished.tail.is_empty());
+
+        assert!(Finished::spawn(&["ibark-not-found".to_string()], 0).is_err());
+        Ok(())
+    }
+}
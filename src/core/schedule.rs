@@ -0,0 +1,389 @@
+use anyhow::{anyhow, Context};
+use chrono::{DateTime, Local, NaiveDate, NaiveDateTime, NaiveTime, TimeZone};
+use std::{collections::HashMap, str::FromStr};
+
+#[derive(clap::Args, Debug)]
+pub struct ScheduleArgs {
+    /// Schedule name from the config file, all schedules if empty
+    #[arg(required = false, value_hint = clap::ValueHint::Other)]
+    pub names: Vec<String>,
+}
+
+#[derive(Clone, Debug, Default, serde::Deserialize)]
+#[serde(default)]
+pub struct Schedule {
+    pub at: Option<String>,
+    pub cron: Option<String>,
+
+    pub contexts: super::api::Notification,
+    pub devices: Vec<String>,
+    pub template: Option<String>,
+    pub vars: HashMap<String, String>,
+
+    /// `at` resolved once, a time without date is not moved to the next day again
+    #[serde(skip)]
+    pub _at: Option<DateTime<Local>>,
+
+    #[serde(skip)]
+    pub _next: Option<DateTime<Local>>,
+}
+
+impl Schedule {
+    /// `RFC3339`, `%Y-%m-%d %H:%M[:%S]` or `%H:%M[:%S]` in local time,
+    /// a time without date is the next one from now.
+    pub fn parse_at(s: &str) -> anyhow::Result<DateTime<Local>> {
+        let context = || format!("parse schedule_at `{s}` failed");
+        let s = s.trim();
+
+        if let Ok(v) = DateTime::parse_from_rfc3339(s) {
+            return Ok(v.with_timezone(&Local));
+        }
+
+        let naive = ["%Y-%m-%d %H:%M:%S", "%Y-%m-%d %H:%M", "%Y-%m-%dT%H:%M:%S"]
+            .iter()
+            .find_map(|f| NaiveDateTime::parse_from_str(s, f).ok());
+
+        let naive = match naive {
+            Some(v) => v,
+            None => {
+                let time = ["%H:%M:%S", "%H:%M"]
+                    .iter()
+                    .find_map(|f| NaiveTime::parse_from_str(s, f).ok())
+                    .ok_or_else(|| anyhow!("not match `RFC3339|%Y-%m-%d %H:%M[:%S]|%H:%M[:%S]`"))
+                    .with_context(context)?;
+
+                let today: NaiveDate = Local::now().date_naive();
+                let v = today.and_time(time);
+                if Self::from_local(v)? <= Local::now() {
+                    today.succ_opt().unwrap_or(today).and_time(time)
+                } else {
+                    v
+                }
+            }
+        };
+
+        Self::from_local(naive).with_context(context)
+    }
+
+    /// Standard 5 fields are accepted, seconds are filled with `0`.
+    pub fn parse_cron(s: &str) -> anyhow::Result<cron::Schedule> {
+        let s = s.trim();
+        let expr = if s.split_whitespace().count() == 5 {
+            format!("0 {s}")
+        } else {
+            s.to_string()
+        };
+
+        cron::Schedule::from_str(&expr)
+            .map_err(|err| anyhow!("{err}"))
+            .with_context(|| format!("parse schedule_cron `{s}` failed"))
+    }
+
+    pub fn parse_delay(s: &str) -> anyhow::Result<std::time::Duration> {
+        humantime::parse_duration(s.trim())
+            .with_context(|| format!("parse send_delay `{s}` failed, e.g. `90s` `10m` `1h30m`"))
+    }
+
+    /// Sleep in short steps, so a suspended machine wakes up on time.
+    pub fn sleep_until(t: DateTime<Local>) {
+        loop {
+            let now = Local::now();
+            if now >= t {
+                return;
+            }
+            let d = (t - now).to_std().unwrap_or_default();
+            std::thread::sleep(d.min(std::time::Duration::from_secs(60)));
+        }
+    }
+
+    pub fn next_after(&self, after: &DateTime<Local>) -> anyhow::Result<Option<DateTime<Local>>> {
+        if let Some(cron) = &self.cron {
+            return Ok(Self::parse_cron(cron)?.after(after).next());
+        }
+        if let Some(at) = self._at {
+            return Ok((at > *after).then_some(at));
+        }
+        Ok(None)
+    }
+
+    /// Resolve `at` against now, once.
+    pub fn resolve_at(&mut self) -> anyhow::Result<()> {
+        if let Some(at) = &self.at {
+            self._at = Some(Self::parse_at(at)?);
+        }
+        Ok(())
+    }
+
+    pub fn verify(&self) -> anyhow::Result<()> {
+        match (&self.at, &self.cron) {
+            (Some(_), Some(_)) => return Err(anyhow!("schedule_at conflicts with schedule_cron")),
+            (None, None) => return Err(anyhow!("schedule_at or schedule_cron is required")),
+            (Some(at), None) => {
+                Self::parse_at(at)?;
+            }
+            (None, Some(cron)) => {
+                Self::parse_cron(cron)?;
+            }
+        }
+
+        if self.devices.is_empty() {
+            return Err(anyhow!("schedule_devices is empty"));
+        }
+        Ok(())
+    }
+
+    pub fn send_args(&self) -> super::send::SendArgs {
+        super::send::SendArgs {
+            contexts: Vec::new(),
+            body: None,
+            body_file: None,
+            body_limit: None,
+            devices: self.devices.clone(),
+            exclude: Vec::new(),
+            limit_conn: None,
+            outbox: false,
+            batch: false,
+            template: self.template.clone(),
+            vars: self.vars.clone().into_iter().collect(),
+            at: None,
+            delay: None,
+            notification: Box::new(self.contexts.clone()),
+        }
+    }
+
+    fn from_local(naive: NaiveDateTime) -> anyhow::Result<DateTime<Local>> {
+        Local
+            .from_local_datetime(&naive)
+            .earliest()
+            .ok_or_else(|| anyhow!("`{naive}` not exists in local time"))
+    }
+}
+
+#[derive(Default, serde::Deserialize)]
+#[serde(default)]
+pub struct ScheduleConf<'a> {
+    #[serde(borrow, flatten)]
+    pub common: super::conf::Common<'a>,
+
+    pub schedules: HashMap<String, Schedule>,
+}
+
+impl<'a> std::fmt::Debug for ScheduleConf<'a> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let mut f: std::fmt::DebugStruct<'_, '_> =
+            f.debug_struct(std::any::type_name::<Self>().split("::").last().unwrap());
+
+        // flatten
+        f.field("remote", &self.common.remote);
+        f.field("user_agent", &self.common.user_agent);
+        self.common.debug_http(&mut f);
+        f.field("profile", &self.common._profile);
+
+        f.field("schedules", &self.schedules);
+
+        if self.common._dump_hide {
+            f.field("_config", &self.common._config);
+        }
+
+        f.finish()
+    }
+}
+
+impl<'a> ScheduleConf<'a> {
+    pub fn dump(mut self) -> anyhow::Result<()> {
+        self.common.remote = super::bark::Remote::dump(&self.common.remote)?.into();
+
+        // schedule devices may be full input as well as names
+        for schedule in self.schedules.values_mut() {
+            for device in schedule.devices.iter_mut() {
+                if let Ok(v) = super::bark::Device::dump(device) {
+                    *device = v;
+                }
+            }
+        }
+
+        println!("{:#?}", self);
+        Ok(())
+    }
+
+    pub fn from_cmd(global: super::cmd::GlobalOptions, args: ScheduleArgs) -> anyhow::Result<Self> {
+        let mut fb = if global.config_file_paths.is_empty() {
+            super::conf::FileBuilder::with_preset()?
+        } else {
+            super::conf::FileBuilder::from_cmd_global_options(global.config_file_paths)?
+        };
+        fb = fb.with_profile(global.profile)?;
+
+        fb.builder = super::conf::Common::builder_default(fb.builder)?
+            .set_override_option("remote", global.remote)?
+            .set_override_option("user_agent", global.user_agent)?;
+        fb.builder = super::conf::Common::set_override_http(fb.builder, global.connection)?;
+
+        let mut _self: Self = fb.builder.build()?.try_deserialize()?;
+        _self.common._config = super::conf::FileDisplay::new(fb.sources);
+        _self.common._profile = fb.profile;
+        _self.common._dump_hide = global.dump_level >= 2;
+
+        // real
+        _self
+            .common
+            .verify(&mut super::secret::Secrets::default())?;
+
+        if !args.names.is_empty() {
+            for name in args.names.iter() {
+                if !_self.schedules.contains_key(name) {
+                    return Err(anyhow!("schedule `{name}` not found"));
+                }
+            }
+            _self.schedules.retain(|k, _| args.names.contains(k));
+        }
+        if _self.schedules.is_empty() {
+            return Err(anyhow!("schedules is empty"));
+        }
+
+        let now = Local::now();
+        for (name, schedule) in _self.schedules.iter_mut() {
+            schedule
+                .verify()
+                .and_then(|_| schedule.resolve_at())
+                .with_context(|| format!("invalid schedule `{name}`"))?;
+            schedule._next = schedule.next_after(&now)?;
+            if schedule._next.is_none() {
+                super::cli::Output::warn(&format!("schedule `{name}` is in the past, skip"));
+            }
+        }
+
+        Ok(_self)
+    }
+}
+
+pub fn exec(global: super::cmd::GlobalOptions, args: ScheduleArgs) -> anyhow::Result<()> {
+    let dump_level = global.dump_level;
+    let mut conf = ScheduleConf::from_cmd(global.clone(), args)?;
+    if dump_level > 0 {
+        return conf.dump();
+    }
+
+    super::cli::Output::exec(&format!(
+        "Schedule -R {} {}",
+        super::bark::Remote::scheme_host_port(&conf.common.remote)?,
+        conf.schedules.len()
+    ));
+
+    let mut names = conf.schedules.keys().cloned().collect::<Vec<_>>();
+    names.sort();
+
+    while let Some(next) = conf.schedules.values().filter_map(|v| v._next).min() {
+        Schedule::sleep_until(next);
+
+        for name in names.iter() {
+            let schedule = conf.schedules.get_mut(name).unwrap();
+            if schedule._next != Some(next) {
+                continue;
+            }
+
+            super::cli::Output::exec(&format!("Schedule {name} at {}", next.to_rfc3339()));
+            // the config is merged again, changes are picked up without restart
+            if let Err(err) = super::send::exec(global.clone(), schedule.send_args()) {
+                super::cli::Output::warn(&format!("schedule `{name}` failed: {err:#}"));
+            }
+            if super::cli::Main::is_request_once_err() {
+                super::cli::Output::warn(&format!(
+                    "schedule `{name}` at least one request error occurred"
+                ));
+                super::cli::Main::set_request_once_err(false);
+            }
+
+            schedule._next = schedule.next_after(&next)?;
+        }
+    }
+
+    super::cli::Output::exec("All schedules done");
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::*;
+    use super::*;
+    use crate::util::tests::*;
+    use clap::Parser;
+
+    #[test]
+    fn test_parse() -> anyhow::Result<()> {
+        let now = Local::now();
+
+        dbg!(Schedule::parse_at("2030-01-02T03:04:05+08:00")?);
+        dbg!(Schedule::parse_at("2030-01-02 03:04")?);
+        let at = dbg!(Schedule::parse_at("00:00")?);
+        assert!(at > now);
+
+        let cron = Schedule::parse_cron("30 9 * * Mon-Fri")?;
+        dbg!(cron.after(&now).take(3).collect::<Vec<_>>());
+        dbg!(Schedule::parse_cron("0 */5 * * * *")?.after(&now).next());
+
+        assert_eq!(
+            Schedule::parse_delay("1h30m")?,
+            std::time::Duration::from_secs(5400)
+        );
+
+        for err in ["", "tomorrow", "25:00"] {
+            dbg!(Schedule::parse_at(err).unwrap_err());
+        }
+        dbg!(Schedule::parse_cron("* *").unwrap_err());
+        dbg!(Schedule::parse_delay("soon").unwrap_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_next_after() -> anyhow::Result<()> {
+        let now = Local::now();
+        let mut schedule = Schedule {
+            at: Some(
+                (now + chrono::Duration::minutes(2))
+                    .format("%H:%M")
+                    .to_string(),
+            ),
+            ..Default::default()
+        };
+        schedule.resolve_at()?;
+
+        let next = schedule.next_after(&now)?.unwrap();
+        // fired once, not again the next day
+        assert_eq!(schedule.next_after(&next)?, None);
+        Ok(())
+    }
+
+    #[test]
+    fn test_dump() -> anyhow::Result<()> {
+        let config = std::env::temp_dir().join(format!("{}.yaml", random_string(16)));
+        std::fs::write(
+            &config,
+            format!(
+                r#"
+schedules:
+  standup:
+    cron: "30 9 * * Mon-Fri"
+    devices: [phone, "d://{}"]
+    contexts:
+      t: Standup
+  reminder:
+    at: "2030-01-02 03:04"
+    devices: [phone]
+    template: deploy
+    vars:
+      service: api
+"#,
+                random_string(22)
+            ),
+        )?;
+
+        let cli = cli::Main::parse_from(["", "schedule", "-C", config.to_str().unwrap(), "-DD"]);
+        let ret = match cli.command.unwrap() {
+            cmd::Commands::Schedule(args) => exec(cli.global, args),
+            _ => unreachable!(),
+        };
+        std::fs::remove_file(config)?;
+        ret
+    }
+}
@@ -0,0 +1,131 @@
+use anyhow::{anyhow, Context};
+use std::collections::HashMap;
+
+/// Resolve `${env:NAME}`, `${file:PATH}` and `${cmd:COMMAND}` in config values,
+/// `$${` is a literal `${`.
+///
+/// Resolved values are secrets, never put them in errors.
+#[derive(Default)]
+pub struct Secrets {
+    // a command may prompt, ask once per run
+    cache: HashMap<String, String>,
+}
+
+impl Secrets {
+    pub fn resolve(&mut self, s: &str) -> anyhow::Result<String> {
+        if !s.contains("${") {
+            return Ok(s.to_string());
+        }
+
+        let mut resolved = String::with_capacity(s.len());
+        let mut rest = s;
+
+        while let Some(start) = rest.find("${") {
+            if rest[..start].ends_with('$') {
+                resolved.push_str(&rest[..start - 1]);
+                resolved.push_str("${");
+                rest = &rest[start + 2..];
+                continue;
+            }
+            resolved.push_str(&rest[..start]);
+            rest = &rest[start + 2..];
+
+            let end = rest
+                .find('}')
+                .ok_or_else(|| anyhow!("unclosed secret `${{{rest}`"))?;
+            let reference = &rest[..end];
+            if !self.cache.contains_key(reference) {
+                let v = Self::lookup(reference)?;
+                self.cache.insert(reference.to_string(), v);
+            }
+            resolved.push_str(self.cache.get(reference).unwrap());
+            rest = &rest[end + 1..];
+        }
+        resolved.push_str(rest);
+
+        Ok(resolved)
+    }
+
+    fn lookup(reference: &str) -> anyhow::Result<String> {
+        let (kind, arg) = reference
+            .split_once(':')
+            .ok_or_else(|| anyhow!("secret `${{{reference}}}` not match `env|file|cmd:...`"))?;
+        let arg = arg.trim();
+
+        match kind.trim() {
+            "env" => std::env::var(arg).with_context(|| format!("secret_env `{arg}` not set")),
+            "file" => {
+                let v = std::fs::read_to_string(arg)
+                    .with_context(|| format!("read secret_file `{arg}` failed"))?;
+                Ok(v.trim_end_matches(['\r', '\n']).to_string())
+            }
+            "cmd" => {
+                #[cfg(unix)]
+                let output = std::process::Command::new("sh").args(["-c", arg]).output();
+                #[cfg(windows)]
+                let output = std::process::Command::new("cmd").args(["/C", arg]).output();
+
+                let output = output.with_context(|| format!("run secret_cmd `{arg}` failed"))?;
+                if !output.status.success() {
+                    return Err(anyhow!("secret_cmd `{arg}` {}", output.status));
+                }
+                let v = String::from_utf8(output.stdout)
+                    .with_context(|| format!("secret_cmd `{arg}` output is not utf-8"))?;
+                Ok(v.trim_end_matches(['\r', '\n']).to_string())
+            }
+            kind => Err(anyhow!(
+                "unsupported secret `{kind}`, not match `env|file|cmd`"
+            )),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::util::tests::*;
+
+    #[test]
+    fn test_resolve() -> anyhow::Result<()> {
+        let mut secrets = Secrets::default();
+        let value = random_string(22);
+
+        let env = format!("IBARK_TEST_{}", random_string(8).to_uppercase());
+        std::env::set_var(&env, &value);
+        assert_eq!(
+            secrets.resolve(&format!("d://${{env:{env}}}"))?,
+            format!("d://{value}")
+        );
+
+        let file = std::env::temp_dir().join(random_string(16));
+        std::fs::write(&file, format!("{value}\n"))?;
+        assert_eq!(
+            secrets.resolve(&format!("${{file:{}}}", file.display()))?,
+            value
+        );
+        std::fs::remove_file(file)?;
+
+        #[cfg(unix)]
+        assert_eq!(
+            secrets.resolve(&format!("${{cmd:echo {value}}}:${{cmd:echo {value}}}"))?,
+            format!("{value}:{value}")
+        );
+
+        assert_eq!(secrets.resolve("no secrets")?, "no secrets");
+        assert_eq!(secrets.resolve("$${env:HOME}")?, "${env:HOME}");
+
+        for err in [
+            "${env:IBARK_TEST_NOT_SET}".to_string(),
+            "${file:/ibark/not/found}".to_string(),
+            "${cmd:exit 1}".to_string(),
+            "${vault:bark}".to_string(),
+            "${env}".to_string(),
+            "${env:HOME".to_string(),
+        ] {
+            let err = dbg!(secrets.resolve(&err).unwrap_err());
+            assert!(!format!("{err:#}").contains(&value));
+        }
+        std::env::remove_var(&env);
+        Ok(())
+    }
+}
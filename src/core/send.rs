@@ -1,3 +1,4 @@
+use anyhow::Context;
 use indicatif::ProgressBar;
 use std::{collections::HashMap, sync::Arc};
 use tokio::{runtime::Runtime, sync::Semaphore, task::JoinSet};
@@ -16,20 +17,102 @@ pub struct SendArgs {
     )]
     pub contexts: Vec<(String, String)>,
 
-    /// Device name from the config file or your full input
+    /// Specify notification body, `-` reads from stdin
+    #[arg(
+        long,
+        value_name = "BODY",
+        value_hint = clap::ValueHint::Other,
+        conflicts_with = "body_file",
+        long_help = "Specify notification body, `-` reads from stdin\n\nUnlike -c 'b=...', no escaping is needed and -c 'b=...' is overridden"
+    )]
+    pub body: Option<String>,
+
+    /// Specify notification body from file
+    #[arg(long, value_name = "PATH", value_hint = clap::ValueHint::FilePath)]
+    pub body_file: Option<std::path::PathBuf>,
+
+    #[arg(
+        long,
+        value_name = "BYTES",
+        help = format!("Specify max body bytes, longer body will be truncated [fallback: {}]", super::conf::fallback_body_limit())
+    )]
+    pub body_limit: Option<usize>,
+
+    /// Device name, @group or tag:name from the config file or your full input
     #[arg(
         required = true,
         value_hint = clap::ValueHint::Other,
     )]
     pub devices: Vec<String>,
 
+    /// Skip devices, same syntax as DEVICES
+    #[arg(short = 'x', long, value_name = "DEVICE", value_hint = clap::ValueHint::Other)]
+    pub exclude: Vec<String>,
+
     #[arg(
         short = 'l',
         long,
         value_name = "LIMIT",
-        help = format!("Specify max concurrent tasks [fallback: {}]", super::conf::fallback_limit_conn())
+        help = format!("Specify max concurrent tasks per remote [fallback: {}]", super::conf::fallback_limit_conn())
     )]
     pub limit_conn: Option<u16>,
+
+    /// Save failed notifications to the outbox, see `ibark outbox`
+    #[arg(
+        long,
+        long_help = "Save failed notifications to the outbox, see `ibark outbox`\n\nOnly connection errors, 5xx and 429 are saved"
+    )]
+    pub outbox: bool,
+
+    /// Send plaintext devices in one request, same as `batch: true`
+    #[arg(
+        long,
+        long_help = "Send plaintext devices in one request, same as `batch: true`\n\nd:// devices of the same remote, contexts and policy are sent in one JSON request to `/push` with `device_keys`, which needs bark-server v2, so it is off by default. aes:// devices and the `path` request style are always sent one by one"
+    )]
+    pub batch: bool,
+
+    /// Specify template name from the config file
+    #[arg(
+        short = 'T',
+        long,
+        value_name = "NAME",
+        value_hint = clap::ValueHint::Other,
+        long_help = "Specify template name from the config file\n\nRendered contexts override the config contexts, -c overrides both"
+    )]
+    pub template: Option<String>,
+
+    /// Specify template variables
+    #[arg(
+        long = "var",
+        value_name = "KEYVAL",
+        value_hint = clap::ValueHint::Other,
+        requires = "template",
+        value_parser = super::cmd::parse_key_val::<String,String>,
+    )]
+    pub vars: Vec<(String, String)>,
+
+    /// Send at local time, e.g. `18:30` `2023-08-01 09:00` or RFC3339
+    #[arg(
+        long,
+        value_name = "TIME",
+        value_hint = clap::ValueHint::Other,
+        conflicts_with = "delay",
+        value_parser = super::schedule::Schedule::parse_at,
+    )]
+    pub at: Option<chrono::DateTime<chrono::Local>>,
+
+    /// Send after duration, e.g. `90s` `10m` `1h30m`
+    #[arg(
+        long,
+        value_name = "DURATION",
+        value_hint = clap::ValueHint::Other,
+        value_parser = super::schedule::Schedule::parse_delay,
+    )]
+    pub delay: Option<std::time::Duration>,
+
+    /// Typed contexts of `run` and `schedule`, under -c
+    #[arg(skip)]
+    pub notification: Box<super::api::Notification>,
 }
 
 #[derive(Default, serde::Deserialize)]
@@ -38,9 +121,31 @@ pub struct SendConf<'a> {
     #[serde(borrow, flatten)]
     pub common: super::conf::Common<'a>,
 
-    pub contexts: HashMap<String, String>,
-    pub devices: HashMap<String, String>,
+    pub batch: bool,
+    pub body_limit: usize,
+    pub contexts: super::api::Notification,
+    pub devices: HashMap<String, super::bark::DeviceConf>,
+    /// members are selectors as well, nested groups included
+    pub groups: HashMap<String, Vec<String>>,
     pub limit_conn: u16,
+    pub outbox: bool,
+    pub outbox_dir: String,
+    pub request_style: String,
+    pub retries: u32,
+    pub retry_backoff: String,
+    pub templates: HashMap<String, HashMap<String, String>>,
+    pub timeout: String,
+
+    #[serde(skip)]
+    pub _policy: super::api::RequestPolicy,
+
+    /// template, -c and body, over the device contexts
+    #[serde(skip)]
+    pub _overrides: super::api::Notification,
+
+    /// selected ones before `resolve`, what the outbox keeps
+    #[serde(skip)]
+    pub _unresolved: HashMap<String, super::bark::DeviceConf>,
 }
 
 impl<'a> std::fmt::Debug for SendConf<'a> {
@@ -51,10 +156,22 @@ impl<'a> std::fmt::Debug for SendConf<'a> {
         // flatten
         f.field("remote", &self.common.remote);
         f.field("user_agent", &self.common.user_agent);
+        self.common.debug_http(&mut f);
+        f.field("profile", &self.common._profile);
 
+        f.field("batch", &self.batch);
+        f.field("body_limit", &self.body_limit);
         f.field("contexts", &self.contexts);
         f.field("devices", &self.devices);
+        f.field("groups", &self.groups);
         f.field("limit_conn", &self.limit_conn);
+        f.field("outbox", &self.outbox);
+        f.field("outbox_dir", &self.outbox_dir);
+        f.field("request_style", &self.request_style);
+        f.field("retries", &self.retries);
+        f.field("retry_backoff", &self.retry_backoff);
+        f.field("templates", &self.templates);
+        f.field("timeout", &self.timeout);
 
         if self.common._dump_hide {
             f.field("_config", &self.common._config);
@@ -68,8 +185,9 @@ impl<'a> SendConf<'a> {
     pub fn builder_default(
         builder: super::conf::SyncBuilder,
     ) -> anyhow::Result<super::conf::SyncBuilder> {
+        // by field, aliases are renamed so within each source, see `conf::Normalized`
         let default_contexts = crate::hash_map! {
-            "a" => "1",
+            "auto_copy" => "1",
             "level" => "active"
         }
         .into_iter()
@@ -77,16 +195,24 @@ impl<'a> SendConf<'a> {
         .collect::<HashMap<String, String>>();
 
         Ok(super::conf::Common::builder_default(builder)?
+            .set_default("batch", false)?
+            .set_default("body_limit", super::conf::fallback_body_limit() as u64)?
             .set_default("contexts", default_contexts)?
-            .set_default("limit_conn", super::conf::fallback_limit_conn())?)
+            .set_default("limit_conn", super::conf::fallback_limit_conn())?
+            .set_default("outbox", false)?
+            .set_default("outbox_dir", super::conf::fallback_outbox_dir())?
+            .set_default("request_style", super::conf::fallback_request_style())?
+            .set_default("retries", super::conf::fallback_retries())?
+            .set_default("retry_backoff", super::conf::fallback_retry_backoff())?
+            .set_default("timeout", super::conf::fallback_timeout())?)
     }
 
     pub fn dump(mut self) -> anyhow::Result<()> {
         self.common.remote = super::bark::Remote::dump(&self.common.remote)?.into();
 
         let mut devices = HashMap::with_capacity(self.devices.len());
-        for (name, input) in self.devices.into_iter() {
-            devices.insert(name, super::bark::Device::dump(&input)?);
+        for (name, device) in self.devices.into_iter() {
+            devices.insert(name, device.dump()?);
         }
         self.devices = devices;
 
@@ -100,76 +226,248 @@ impl<'a> SendConf<'a> {
         } else {
             super::conf::FileBuilder::from_cmd_global_options(global.config_file_paths)?
         };
+        fb = fb.with_profile(global.profile)?;
 
         fb.builder = Self::builder_default(fb.builder)?
             .set_override_option("remote", global.remote)?
             .set_override_option("user_agent", global.user_agent)?
-            .set_override(
-                "contexts",
-                args.contexts.into_iter().collect::<HashMap<_, _>>(),
-            )?
-            .set_override_option("limit_conn", args.limit_conn)?;
+            .set_override_option("batch", args.batch.then_some(true))?
+            .set_override_option("body_limit", args.body_limit.map(|v| v as u64))?
+            .set_override_option("limit_conn", args.limit_conn)?
+            .set_override_option("outbox", args.outbox.then_some(true))?;
+        fb.builder = super::conf::Common::set_override_http(fb.builder, global.connection)?;
 
         let mut _self: Self = fb.builder.build()?.try_deserialize()?;
         _self.common._config = super::conf::FileDisplay::new(fb.sources);
+        _self.common._profile = fb.profile;
         _self.common._dump_hide = global.dump_level >= 2;
 
         // real
-        super::bark::Remote::verify(&_self.common.remote)?;
-        _self.contexts = super::bark::Contexts::verify(_self.contexts)?;
-        _self.devices = super::bark::Device::find_merge(&_self.devices, args.devices);
+        let mut secrets = super::secret::Secrets::default();
+        _self.common.verify(&mut secrets)?;
+        _self._policy = super::api::RequestPolicy::new(
+            &_self.timeout,
+            _self.retries,
+            &_self.retry_backoff,
+            &_self.request_style,
+        )?;
+        // config < device < template < run|schedule < -c
+        if let Some(name) = args.template {
+            let vars = args.vars.into_iter().collect();
+            let rendered = super::template::Template::render(&_self.templates, &name, &vars)?;
+            _self
+                ._overrides
+                .merge(&super::api::Notification::from_map(rendered)?);
+        }
+        _self._overrides.merge(&args.notification);
+        _self
+            ._overrides
+            .merge(&super::api::Notification::from_pairs(args.contexts)?);
+
+        // raw body, not escaped as with -c
+        let body = match (args.body, args.body_file) {
+            (Some(v), _) if v == "-" => Some(Self::read_body(std::io::stdin().lock())?),
+            (Some(v), _) => Some(v),
+            (None, Some(p)) => Some(Self::read_body(
+                std::fs::File::open(&p)
+                    .with_context(|| format!("invalid path `{}`", p.display()))?,
+            )?),
+            (None, None) => None,
+        };
+        if let Some(body) = body {
+            let body = body.trim_end();
+            if !body.is_empty() {
+                _self._overrides.body = Some(body.to_string());
+            }
+        }
+        _self.contexts.merge(&_self._overrides);
+        if let Some(body) = _self.contexts.body.as_mut() {
+            Self::limit_body(body, _self.body_limit);
+            if let Some(v) = _self._overrides.body.as_mut() {
+                *v = body.to_string();
+            }
+        }
+
+        _self.devices =
+            super::bark::Device::select(&_self.devices, &_self.groups, args.devices, args.exclude)?;
+        _self._unresolved = _self.devices.clone();
+        // only the selected ones, others may hold commands not meant for this run
+        for (name, device) in _self.devices.iter_mut() {
+            *device = device
+                .resolve(&mut secrets)
+                .and_then(|v| v.verify().map(|_| v))
+                .with_context(|| format!("invalid device `{name}`"))?;
+        }
 
         Ok(_self)
     }
+
+    /// config < device < template < -c
+    pub fn contexts_for(
+        &self,
+        device: &super::bark::DeviceConf,
+    ) -> anyhow::Result<HashMap<String, String>> {
+        let mut contexts = self.contexts.clone();
+        let device_contexts = device.contexts();
+        if !device_contexts.is_empty() {
+            contexts.merge(&device_contexts);
+            contexts.merge(&self._overrides);
+            // a device body is not limited by `from_cmd`
+            if let Some(body) = contexts.body.as_mut() {
+                Self::limit_body(body, self.body_limit);
+            }
+        }
+        contexts.contexts()
+    }
+
+    fn limit_body(body: &mut String, limit: usize) {
+        if body.len() > limit {
+            super::cli::Output::warn(&format!("body len `{}` > {limit}, truncated", body.len()));
+            *body = crate::util::truncate_utf8(body, limit).into_owned();
+        }
+    }
+
+    fn read_body<R: std::io::Read>(mut r: R) -> anyhow::Result<String> {
+        let mut buf = Vec::new();
+        r.read_to_end(&mut buf)
+            .with_context(|| "read bark_context_body failed")?;
+        // journalctl and friends may print invalid utf-8
+        Ok(String::from_utf8_lossy(&buf).into_owned())
+    }
+}
+
+/// name, input, remote and contexts
+type Target<'a> = (&'a String, String, String, HashMap<String, String>);
+
+/// Indexes of `targets` per request, plaintext devices of the same remote, policy
+/// and contexts are coalesced, unless `batch` is off or the style is `path`.
+/// `batch-json` always coalesces.
+fn batches(
+    client: &super::api::Client,
+    targets: &[Target],
+    batch: bool,
+) -> anyhow::Result<Vec<Vec<usize>>> {
+    use super::api::RequestStyle;
+
+    let mut batches: Vec<Vec<usize>> = Vec::with_capacity(targets.len());
+    let mut coalesced: HashMap<_, usize> = HashMap::new();
+    for (index, (_, input, remote, contexts)) in targets.iter().enumerate() {
+        let policy = client.policy(input)?;
+        let is_batch = match policy.style {
+            RequestStyle::BatchJson => true,
+            RequestStyle::Path => false,
+            _ => batch,
+        };
+        if is_batch && super::bark::Device::plain_key(input)?.is_some() {
+            let contexts = contexts
+                .iter()
+                .collect::<std::collections::BTreeMap<_, _>>();
+            match coalesced.entry((remote, contexts, policy)) {
+                std::collections::hash_map::Entry::Occupied(v) => {
+                    batches[*v.get()].push(index);
+                    continue;
+                }
+                std::collections::hash_map::Entry::Vacant(v) => {
+                    v.insert(batches.len());
+                }
+            }
+        }
+        batches.push(vec![index]);
+    }
+    Ok(batches)
 }
 
 pub fn exec(global: super::cmd::GlobalOptions, args: SendArgs) -> anyhow::Result<()> {
     let dump_level = global.dump_level;
+    let deferred = match (args.at, args.delay) {
+        (Some(at), _) => Some(at),
+        (None, Some(delay)) => Some(chrono::Local::now() + chrono::Duration::from_std(delay)?),
+        (None, None) => None,
+    };
+
     let conf = SendConf::from_cmd(global, args)?;
     if dump_level > 0 {
         return conf.dump();
     }
 
+    if let Some(at) = deferred {
+        super::cli::Output::exec(&format!("Wait until {}", at.to_rfc3339()));
+        super::schedule::Schedule::sleep_until(at);
+    }
+
     let (pb_multi, pb_main) = super::cli::Main::create_multi_progress(conf.devices.len() as u64)?;
-    let semaphore = Arc::new(Semaphore::new(conf.limit_conn as usize));
 
-    let client = reqwest::Client::builder()
-        .user_agent(conf.common.user_agent.as_ref())
-        .build()?;
+    let client = super::api::Client::new(&conf.common.remote)?
+        .with_http(conf.common.http_options()?)?
+        .with_policy(conf._policy);
 
     let mut join_set = JoinSet::new();
     Runtime::new()?.block_on(async {
         let ret: anyhow::Result<_> = Ok(());
 
-        for (index, (name, input)) in conf.devices.into_iter().enumerate() {
-            let req = super::bark::Device::new_request(
-                &input,
-                &client,
-                &conf.common.remote,
-                &conf.contexts,
-            )?;
+        // resolved and indexed for the outbox
+        let mut targets = Vec::with_capacity(conf.devices.len());
+        for (name, device) in conf.devices.iter() {
+            let remote = device
+                .remote()?
+                .unwrap_or_else(|| conf.common.remote.to_string());
+            targets.push((name, device.input()?, remote, conf.contexts_for(device)?));
+        }
+        // grouped by remote
+        targets.sort_by(|a, b| (&a.2, a.0).cmp(&(&b.2, b.0)));
+
+        let mut remotes = targets.iter().map(|v| v.2.as_str()).collect::<Vec<_>>();
+        remotes.dedup();
+        // per remote, a slow one does not hold up the others
+        let mut semaphores = HashMap::with_capacity(remotes.len());
+        for remote in remotes.into_iter() {
+            semaphores.insert(remote, Arc::new(Semaphore::new(conf.limit_conn as usize)));
+            pb_multi.println(super::cli::Output::exec_string(&format!(
+                "Send -R {} -l {} {}",
+                super::bark::Remote::scheme_host_port(remote)?,
+                conf.limit_conn,
+                targets.iter().filter(|v| v.2 == remote).count()
+            )))?;
+        }
+
+        for batch in batches(&client, &targets, conf.batch)?.into_iter() {
+            let inputs = batch
+                .iter()
+                .map(|v| targets[*v].1.as_str())
+                .collect::<Vec<_>>();
+            let (req, policy) = client.batch_request(&inputs, &targets[batch[0]].3)?;
+            let mut keys = Vec::with_capacity(inputs.len());
+            if inputs.len() > 1 {
+                for input in inputs.iter() {
+                    keys.extend(super::bark::Device::plain_key(input)?);
+                }
+            }
+            let inames = batch
+                .iter()
+                .map(|v| (*v, targets[*v].0.to_string()))
+                .collect();
 
             let pb_task = pb_multi.insert_before(&pb_main, ProgressBar::new(1));
 
-            join_set.spawn(super::cli::Main::request_handle(
-                semaphore.clone(),
+            join_set.spawn(super::cli::Main::batch_handle(
+                semaphores[targets[batch[0]].2.as_str()].clone(),
                 pb_task,
-                (index, name),
+                inames,
+                keys,
                 req,
+                policy,
             ));
         }
 
-        pb_multi.println(super::cli::Output::exec_string(&format!(
-            "Send -R {} -l {}",
-            super::bark::Remote::scheme_host_port(&conf.common.remote)?,
-            conf.limit_conn
-        )))?;
-
+        let mut summary = super::cli::RequestSummary::default();
         while let Some(v) = join_set.join_next().await {
             match v {
                 Ok(res) => {
-                    res?;
-                    pb_main.inc(1);
+                    let res = res?;
+                    pb_main.inc(res.len() as u64);
+                    for v in res.into_iter() {
+                        summary.push(v);
+                    }
                 }
                 Err(err) => {
                     return Err(anyhow::anyhow!(err));
@@ -178,9 +476,40 @@ pub fn exec(global: super::cmd::GlobalOptions, args: SendArgs) -> anyhow::Result
         }
 
         pb_main.finish_with_message("All tasks done");
-        println!("\n");
         // super::cli::Main::warn_request_once_err();
 
+        match super::cli::Output::format() {
+            super::cmd::OutputFormat::Text => {
+                println!("\n");
+                summary.print();
+            }
+            super::cmd::OutputFormat::Json => {
+                summary.results.sort_by_key(|v| v.index);
+                super::cli::Output::json(&summary)?;
+            }
+            // already streamed
+            super::cmd::OutputFormat::Ndjson => {}
+        }
+
+        if conf.outbox {
+            let outbox = super::outbox::Outbox::new(&conf.outbox_dir);
+            let mut saved = 0;
+            for v in summary.results.iter().filter(|v| v.is_retryable) {
+                let (name, _, remote, contexts) = &targets[v.index];
+                let device = &conf._unresolved[name.as_str()];
+                outbox.save(&super::outbox::Entry::new(
+                    remote, name, device, contexts, v,
+                ))?;
+                saved += 1;
+            }
+            if saved > 0 {
+                super::cli::Output::warn(&format!(
+                    "{saved} saved to outbox `{}`, see `ibark outbox`",
+                    outbox.dir().display()
+                ));
+            }
+        }
+
         ret
     })
 }
@@ -224,11 +553,6 @@ mod tests {
         args
     }
 
-    // #[test]
-    fn dump_help() {
-        let cli = cli::Main::parse_from(["", "send", "--help"]);
-    }
-
     #[test]
     fn test_dump() -> anyhow::Result<()> {
         let mut args = test_gen_args(false);
@@ -245,6 +569,310 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn test_batches() -> anyhow::Result<()> {
+        let name = "x".to_string();
+        let remote = "https://hello.world".to_string();
+        let target = |input: String, title: &str| {
+            let contexts = HashMap::from([("title".to_string(), title.to_string())]);
+            (&name, input, remote.clone(), contexts)
+        };
+        let aes = format!(
+            "aes://{}:{}@{}/128/cbc/pkcs7",
+            random_string(16),
+            random_string(16),
+            random_string(22)
+        );
+        let targets = vec![
+            target(format!("d://{}", random_string(22)), "a"),
+            target(aes.clone(), "a"),
+            target(format!("d://{}", random_string(22)), "a"),
+            target(format!("d://{}", random_string(22)), "b"),
+            target(aes, "a"),
+        ];
+
+        let coalesced = vec![vec![0, 2], vec![1], vec![3], vec![4]];
+
+        // form by default, batched only if turned on
+        let client = super::super::api::Client::new(&remote)?;
+        assert_eq!(batches(&client, &targets, true)?, coalesced);
+        assert_eq!(batches(&client, &targets, false)?.len(), 5);
+
+        let client = client.request_style(super::super::api::RequestStyle::Path);
+        assert_eq!(batches(&client, &targets, true)?.len(), 5);
+
+        let client = client.request_style(super::super::api::RequestStyle::BatchJson);
+        assert_eq!(batches(&client, &targets, false)?, coalesced);
+        Ok(())
+    }
+
+    #[test]
+    fn test_dump_with_body_file() -> anyhow::Result<()> {
+        let body_file = std::env::temp_dir().join(random_string(16));
+        std::fs::write(&body_file, "👋".repeat(100) + "\n")?;
+
+        let mut args = test_gen_args(false);
+        args.extend(
+            [
+                "--body-file",
+                body_file.to_str().unwrap(),
+                "--body-limit",
+                "10",
+                "-D",
+            ]
+            .map(String::from),
+        );
+
+        let cli = cli::Main::parse_from(args);
+        let conf = match cli.command.unwrap() {
+            cmd::Commands::Send(args) => SendConf::from_cmd(cli.global, args),
+            _ => unreachable!(),
+        };
+        std::fs::remove_file(body_file)?;
+
+        let body = dbg!(conf?.contexts.body.unwrap());
+        assert_eq!(body, "👋…");
+        Ok(())
+    }
+
+    #[test]
+    fn test_contexts_for_device_table() -> anyhow::Result<()> {
+        let config = std::env::temp_dir().join(format!("{}.yaml", random_string(16)));
+        std::fs::write(
+            &config,
+            format!(
+                r#"
+contexts:
+  s: bell
+  g: config
+devices:
+  phone:
+    key: {}
+    contexts:
+      s: alarm
+      g: device
+  long:
+    key: {}
+    contexts:
+      b: {}
+"#,
+                random_string(22),
+                random_string(22),
+                "👋".repeat(100)
+            ),
+        )?;
+
+        let cli = cli::Main::parse_from([
+            "",
+            "send",
+            "-C",
+            config.to_str().unwrap(),
+            "-c",
+            "g=cmd",
+            "--body-limit",
+            "10",
+            "phone",
+            "long",
+            "-D",
+        ]);
+        let conf = match cli.command.unwrap() {
+            cmd::Commands::Send(args) => SendConf::from_cmd(cli.global, args),
+            _ => unreachable!(),
+        };
+        std::fs::remove_file(config)?;
+        let conf = conf?;
+
+        // config < device < -c
+        let contexts = dbg!(conf.contexts_for(conf.devices.get("phone").unwrap())?);
+        assert_eq!(contexts.get("sound").unwrap(), "alarm");
+        assert_eq!(contexts.get("group").unwrap(), "cmd");
+
+        // the device body is limited as well
+        let contexts = dbg!(conf.contexts_for(conf.devices.get("long").unwrap())?);
+        assert_eq!(contexts.get("body").unwrap(), "👋…");
+        conf.dump()
+    }
+
+    #[test]
+    fn test_env_source() -> anyhow::Result<()> {
+        // tests run in parallel, only a device nobody else selects
+        let name = random_string(16).to_lowercase();
+        let key = random_string(22);
+        let env = format!("IBARK_DEVICES__{}", name.to_uppercase());
+        std::env::set_var(&env, format!("d://{key}"));
+
+        let cli = cli::Main::parse_from(["", "send", &name, "-DD"]);
+        let conf = match cli.command.unwrap() {
+            cmd::Commands::Send(args) => SendConf::from_cmd(cli.global, args),
+            _ => unreachable!(),
+        };
+        std::env::remove_var(&env);
+        let conf = conf?;
+
+        assert_eq!(
+            conf.devices.get(&name).unwrap().input()?,
+            format!("d://{key}")
+        );
+        assert!(conf.common._config.env.contains(&env));
+        conf.dump()
+    }
+
+    #[test]
+    fn test_profile() -> anyhow::Result<()> {
+        let config = std::env::temp_dir().join(format!("{}.yaml", random_string(16)));
+        std::fs::write(
+            &config,
+            format!(
+                r#"
+remote: https://prod.example.com
+contexts:
+  g: prod
+devices:
+  phone: d://{}
+profiles:
+  staging:
+    remote: https://staging.example.com
+    limit_conn: 1
+    contexts:
+      g: staging
+    devices:
+      tester: d://{}
+"#,
+                random_string(22),
+                random_string(22)
+            ),
+        )?;
+
+        let conf = |profile: &str, devices: &[&str]| {
+            let cli = cli::Main::parse_from(
+                [
+                    "",
+                    "send",
+                    "-C",
+                    config.to_str().unwrap(),
+                    "-P",
+                    profile,
+                    "-D",
+                ]
+                .iter()
+                .chain(devices),
+            );
+            match cli.command.unwrap() {
+                cmd::Commands::Send(args) => SendConf::from_cmd(cli.global, args),
+                _ => unreachable!(),
+            }
+        };
+        let staging = conf("staging", &["phone", "tester"]);
+        let unknown = conf("unknown", &["phone"]);
+        std::fs::remove_file(config)?;
+
+        // overlaid, base devices kept
+        let conf = staging?;
+        assert_eq!(conf.common._profile.as_deref(), Some("staging"));
+        assert_eq!(conf.common.remote, "https://staging.example.com");
+        assert_eq!(conf.limit_conn, 1);
+        assert_eq!(conf.contexts.group.as_deref(), Some("staging"));
+        assert_eq!(conf.devices.len(), 2);
+        assert!(dbg!(format!("{:#}", unknown.unwrap_err()))
+            .contains("config_profile `unknown` not found"));
+        conf.dump()
+    }
+
+    #[test]
+    fn test_context_aliases() -> anyhow::Result<()> {
+        let name = random_string(16).to_lowercase();
+        let env = format!("IBARK_DEVICES__{}__CONTEXTS__G", name.to_uppercase());
+        std::env::set_var(&env, "env");
+
+        let write = |contexts: &str| -> anyhow::Result<std::path::PathBuf> {
+            let config = std::env::temp_dir().join(format!("{}.yaml", random_string(16)));
+            std::fs::write(
+                &config,
+                format!(
+                    "contexts:\n{contexts}\ndevices:\n  {name}:\n    key: {}\n    contexts:\n      group: file\n",
+                    random_string(22)
+                ),
+            )?;
+            Ok(config)
+        };
+        let conf = |config: &std::path::Path| {
+            let cli =
+                cli::Main::parse_from(["", "send", "-C", config.to_str().unwrap(), &name, "-D"]);
+            match cli.command.unwrap() {
+                cmd::Commands::Send(args) => SendConf::from_cmd(cli.global, args),
+                _ => unreachable!(),
+            }
+        };
+        let alias = write("  l: passive")?;
+        let both = write("  l: passive\n  level: critical")?;
+        let (alias_conf, both_conf) = (conf(&alias), conf(&both));
+        std::env::remove_var(&env);
+        std::fs::remove_file(alias)?;
+        std::fs::remove_file(both)?;
+
+        // an alias overrides the default and the env overrides the file, both by field
+        let conf = alias_conf?;
+        assert_eq!(conf.contexts.level, Some(api::Level::Passive));
+        assert_eq!(conf.contexts.auto_copy, Some(true));
+        let contexts = conf.contexts_for(conf.devices.get(&name).unwrap())?;
+        assert_eq!(contexts.get("group").unwrap(), "env");
+
+        assert!(dbg!(format!("{:#}", both_conf.unwrap_err()))
+            .contains("bark_context `contexts.l` conflicts with `contexts.level`"));
+        conf.dump()
+    }
+
+    #[test]
+    fn test_secret_device() -> anyhow::Result<()> {
+        let key = random_string(22);
+        let env = format!("IBARK_TEST_{}", random_string(8).to_uppercase());
+        std::env::set_var(&env, &key);
+
+        let config = std::env::temp_dir().join(format!("{}.yaml", random_string(16)));
+        std::fs::write(
+            &config,
+            format!(
+                r#"
+devices:
+  phone: d://${{env:{env}}}
+  table:
+    key: ${{env:{env}}}
+  unset: d://${{env:IBARK_TEST_NOT_SET}}
+"#
+            ),
+        )?;
+
+        let conf = |devices: &[&str]| {
+            let cli = cli::Main::parse_from(
+                ["", "send", "-C", config.to_str().unwrap(), "-D"]
+                    .iter()
+                    .chain(devices),
+            );
+            match cli.command.unwrap() {
+                cmd::Commands::Send(args) => SendConf::from_cmd(cli.global, args),
+                _ => unreachable!(),
+            }
+        };
+        let ok = conf(&["phone", "table"]);
+        let err = conf(&["unset"]);
+        std::fs::remove_file(config)?;
+        std::env::remove_var(&env);
+
+        let conf = ok?;
+        assert_eq!(
+            conf.devices.get("phone").unwrap().input()?,
+            format!("d://{key}")
+        );
+        assert_eq!(
+            conf.devices.get("table").unwrap().input()?,
+            format!("d://{key}")
+        );
+        // not selected, not resolved
+        assert!(!conf.devices.contains_key("unset"));
+        assert!(dbg!(format!("{:#}", err.unwrap_err())).contains("invalid device `unset`"));
+        conf.dump()
+    }
+
     // need workdir/dev/secret.yaml
     #[test]
     fn test_dump_with_secret() -> anyhow::Result<()> {
@@ -0,0 +1,574 @@
+use anyhow::{anyhow, Context};
+use hyper::{
+    header::CONTENT_TYPE,
+    service::{make_service_fn, service_fn},
+    Body, Method, Request, Response, StatusCode,
+};
+use std::{collections::HashMap, convert::Infallible, net::SocketAddr, sync::Arc};
+use tokio::{runtime::Runtime, sync::Semaphore, task::JoinSet};
+
+#[derive(clap::Args, Debug)]
+pub struct ServerArgs {
+    #[arg(
+        short = 'L',
+        long,
+        value_name = "ADDR",
+        value_hint = clap::ValueHint::Other,
+        help = format!("Specify listen address [fallback: {}]", super::conf::fallback_listen())
+    )]
+    pub listen: Option<String>,
+
+    #[arg(
+        short = 'l',
+        long,
+        value_name = "LIMIT",
+        help = format!("Specify max concurrent tasks [fallback: {}]", super::conf::fallback_limit_conn())
+    )]
+    pub limit_conn: Option<u16>,
+}
+
+#[derive(Default, serde::Deserialize)]
+#[serde(default)]
+pub struct ServerConf<'a> {
+    #[serde(borrow, flatten)]
+    pub common: super::conf::Common<'a>,
+
+    pub contexts: super::api::Notification,
+    pub devices: HashMap<String, super::bark::DeviceConf>,
+    pub limit_conn: u16,
+    pub listen: String,
+    pub request_style: String,
+    pub retries: u32,
+    pub retry_backoff: String,
+    pub timeout: String,
+
+    #[serde(skip)]
+    pub _policy: super::api::RequestPolicy,
+}
+
+impl<'a> std::fmt::Debug for ServerConf<'a> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let mut f: std::fmt::DebugStruct<'_, '_> =
+            f.debug_struct(std::any::type_name::<Self>().split("::").last().unwrap());
+
+        // flatten
+        f.field("remote", &self.common.remote);
+        f.field("user_agent", &self.common.user_agent);
+        self.common.debug_http(&mut f);
+        f.field("profile", &self.common._profile);
+
+        f.field("contexts", &self.contexts);
+        f.field("devices", &self.devices);
+        f.field("limit_conn", &self.limit_conn);
+        f.field("listen", &self.listen);
+        f.field("request_style", &self.request_style);
+        f.field("retries", &self.retries);
+        f.field("retry_backoff", &self.retry_backoff);
+        f.field("timeout", &self.timeout);
+
+        if self.common._dump_hide {
+            f.field("_config", &self.common._config);
+        }
+
+        f.finish()
+    }
+}
+
+impl<'a> ServerConf<'a> {
+    pub fn builder_default(
+        builder: super::conf::SyncBuilder,
+    ) -> anyhow::Result<super::conf::SyncBuilder> {
+        Ok(super::send::SendConf::builder_default(builder)?
+            .set_default("listen", super::conf::fallback_listen())?)
+    }
+
+    pub fn dump(mut self) -> anyhow::Result<()> {
+        self.common.remote = super::bark::Remote::dump(&self.common.remote)?.into();
+
+        let mut devices = HashMap::with_capacity(self.devices.len());
+        for (name, device) in self.devices.into_iter() {
+            devices.insert(name, device.dump()?);
+        }
+        self.devices = devices;
+
+        println!("{:#?}", self);
+        Ok(())
+    }
+
+    pub fn from_cmd(global: super::cmd::GlobalOptions, args: ServerArgs) -> anyhow::Result<Self> {
+        let mut fb = if global.config_file_paths.is_empty() {
+            super::conf::FileBuilder::with_preset()?
+        } else {
+            super::conf::FileBuilder::from_cmd_global_options(global.config_file_paths)?
+        };
+        fb = fb.with_profile(global.profile)?;
+
+        fb.builder = Self::builder_default(fb.builder)?
+            .set_override_option("remote", global.remote)?
+            .set_override_option("user_agent", global.user_agent)?
+            .set_override_option("limit_conn", args.limit_conn)?
+            .set_override_option("listen", args.listen)?;
+        fb.builder = super::conf::Common::set_override_http(fb.builder, global.connection)?;
+
+        let mut _self: Self = fb.builder.build()?.try_deserialize()?;
+        _self.common._config = super::conf::FileDisplay::new(fb.sources);
+        _self.common._profile = fb.profile;
+        _self.common._dump_hide = global.dump_level >= 2;
+
+        // real
+        let mut secrets = super::secret::Secrets::default();
+        _self.common.verify(&mut secrets)?;
+        _self._policy = super::api::RequestPolicy::new(
+            &_self.timeout,
+            _self.retries,
+            &_self.retry_backoff,
+            &_self.request_style,
+        )?;
+        _self
+            .listen
+            .parse::<SocketAddr>()
+            .with_context(|| format!("parse server_listen `{}` failed", _self.listen))?;
+
+        // devices are resolved per push, fail before listening instead
+        for (name, device) in _self.devices.iter_mut() {
+            *device = device
+                .resolve(&mut secrets)
+                .and_then(|v| v.verify().map(|_| v))
+                .with_context(|| format!("invalid server_device `{name}`"))?;
+        }
+
+        Ok(_self)
+    }
+}
+
+/// Same shape as bark-server responses, so existing clients keep working.
+#[derive(Debug, serde::Serialize)]
+struct ServerResponse {
+    code: u16,
+    message: String,
+    timestamp: u64,
+}
+
+impl ServerResponse {
+    fn reply(code: StatusCode, message: impl Into<String>) -> Response<Body> {
+        let timestamp = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map(|v| v.as_secs())
+            .unwrap_or_default();
+
+        let body = serde_json::to_vec(&Self {
+            code: code.as_u16(),
+            message: message.into(),
+            timestamp,
+        })
+        .unwrap_or_default();
+
+        Response::builder()
+            .status(code)
+            .header(CONTENT_TYPE, "application/json; charset=utf-8")
+            .body(Body::from(body))
+            .unwrap()
+    }
+}
+
+/// A parsed `/push` request, see https://github.com/Finb/bark-server/blob/master/docs/API_V2.md#push
+#[derive(Debug, Default)]
+struct Push {
+    devices: Vec<String>,
+    contexts: HashMap<String, String>,
+}
+
+impl Push {
+    fn insert(&mut self, k: String, v: String) {
+        match k.as_str() {
+            "device_key" => self.devices.push(v),
+            "device_keys" => self
+                .devices
+                .extend(v.split(',').map(|v| v.trim().to_string())),
+            _ => {
+                self.contexts.insert(k, v);
+            }
+        }
+    }
+
+    fn from_json(body: &[u8]) -> anyhow::Result<Self> {
+        let map: serde_json::Map<String, serde_json::Value> =
+            serde_json::from_slice(body).with_context(|| "parse server_push_json failed")?;
+
+        let mut _self = Self::default();
+        for (k, v) in map.into_iter() {
+            match v {
+                serde_json::Value::Null => {}
+                serde_json::Value::String(v) => _self.insert(k, v),
+                serde_json::Value::Number(v) => _self.insert(k, v.to_string()),
+                serde_json::Value::Bool(v) => _self.insert(k, v.to_string()),
+                serde_json::Value::Array(v) if k == "device_keys" => {
+                    for v in v.into_iter() {
+                        match v {
+                            serde_json::Value::String(v) => _self.devices.push(v),
+                            _ => return Err(anyhow!("server_push_device_keys not strings")),
+                        }
+                    }
+                }
+                _ => return Err(anyhow!("unsupported server_push_value of `{k}`")),
+            }
+        }
+        Ok(_self)
+    }
+
+    fn from_form(body: &[u8]) -> Self {
+        let mut _self = Self::default();
+        for (k, v) in url::form_urlencoded::parse(body).into_owned() {
+            _self.insert(k, v);
+        }
+        _self
+    }
+}
+
+struct ServerState {
+    /// the remote and the policy of the config
+    client: super::api::Client,
+    contexts: super::api::Notification,
+    devices: HashMap<String, super::bark::DeviceConf>,
+    semaphore: Arc<Semaphore>,
+}
+
+impl ServerState {
+    async fn handle(self: Arc<Self>, req: Request<Body>) -> Result<Response<Body>, Infallible> {
+        let resp = match (req.method(), req.uri().path()) {
+            (&Method::GET, "/healthz") => Response::new(Body::from("ok")),
+            (&Method::GET, "/ping") => ServerResponse::reply(StatusCode::OK, "pong"),
+            (&Method::GET, "/info") => Response::builder()
+                .header(CONTENT_TYPE, "application/json; charset=utf-8")
+                .body(Body::from(format!(
+                    r#"{{"version":"{}"}}"#,
+                    crate::pkg_ver!()
+                )))
+                .unwrap(),
+            (&Method::POST, path) => match Self::push_route(path) {
+                Some(path_device) => match self.push(req, path_device).await {
+                    Ok(v) => ServerResponse::reply(StatusCode::OK, v),
+                    Err((code, err)) => ServerResponse::reply(code, err),
+                },
+                None => ServerResponse::reply(StatusCode::NOT_FOUND, "not found"),
+            },
+            _ => ServerResponse::reply(StatusCode::NOT_FOUND, "not found"),
+        };
+        Ok(resp)
+    }
+
+    /// `/push` or `/:device_key` with the device of the path, `None` for others.
+    fn push_route(path: &str) -> Option<Option<String>> {
+        match path.strip_prefix('/')? {
+            "push" => Some(None),
+            v if !v.is_empty() && !v.contains('/') => Some(Some(v.to_string())),
+            _ => None,
+        }
+    }
+
+    async fn push(
+        &self,
+        req: Request<Body>,
+        path_device: Option<String>,
+    ) -> Result<String, (StatusCode, String)> {
+        let bad_request = |err: anyhow::Error| (StatusCode::BAD_REQUEST, format!("{err:#}"));
+
+        let is_json = req
+            .headers()
+            .get(CONTENT_TYPE)
+            .and_then(|v| v.to_str().ok())
+            .map(|v| v.starts_with("application/json"))
+            .unwrap_or_default();
+
+        let body = hyper::body::to_bytes(req.into_body())
+            .await
+            .map_err(|err| bad_request(err.into()))?;
+
+        let mut push = if is_json {
+            Push::from_json(&body).map_err(bad_request)?
+        } else {
+            Push::from_form(&body)
+        };
+        push.devices.extend(path_device);
+        push.devices.retain(|v| !v.is_empty());
+        push.devices.sort();
+        push.devices.dedup();
+
+        if push.devices.is_empty() {
+            return Err(bad_request(anyhow!("server_push_device_key is empty")));
+        }
+
+        let internal_error =
+            |err: anyhow::Error| (StatusCode::INTERNAL_SERVER_ERROR, format!("{err:#}"));
+        let push_contexts =
+            super::api::Notification::from_map(push.contexts).map_err(bad_request)?;
+
+        let mut join_set = JoinSet::new();
+        for (index, name) in push.devices.into_iter().enumerate() {
+            let device = self
+                .devices
+                .get(&name)
+                .ok_or_else(|| bad_request(anyhow!("server_device `{name}` not found")))?;
+
+            // config < device < push
+            let mut contexts = self.contexts.clone();
+            contexts.merge(&device.contexts());
+            contexts.merge(&push_contexts);
+            let contexts = contexts.contexts().map_err(bad_request)?;
+
+            let input = device.input().map_err(internal_error)?;
+            let (req, policy) = self
+                .client
+                .request(&input, &contexts)
+                .map_err(internal_error)?;
+
+            let semaphore = self.semaphore.clone();
+            join_set.spawn(async move {
+                let _permit = semaphore.acquire_owned().await;
+                // retries are logged by the summary, no progress bar here
+                let pb_task = indicatif::ProgressBar::hidden();
+                super::cli::Main::request_send((index, name), req, policy, &pb_task).await
+            });
+        }
+
+        let mut summary = super::cli::RequestSummary::default();
+        while let Some(v) = join_set.join_next().await {
+            match v {
+                Ok(ret) => summary.push(ret),
+                Err(err) => return Err((StatusCode::INTERNAL_SERVER_ERROR, err.to_string())),
+            }
+        }
+        summary.results.sort_by_key(|v| v.index);
+
+        super::cli::Output::exec(&format!(
+            "Push {} success {} failed",
+            summary.success, summary.failed
+        ));
+        // a document per push with `json`, a line with `ndjson`
+        super::cli::Output::json(&summary).ok();
+
+        if summary.failed == 0 {
+            Ok("success".into())
+        } else {
+            let failed = summary
+                .results
+                .iter()
+                .filter(|v| !v.is_success())
+                .map(|v| format!("{}: {}", v.device, v.reason()))
+                .collect::<Vec<_>>();
+            Err((StatusCode::INTERNAL_SERVER_ERROR, failed.join("; ")))
+        }
+    }
+}
+
+pub fn exec(global: super::cmd::GlobalOptions, args: ServerArgs) -> anyhow::Result<()> {
+    let dump_level = global.dump_level;
+    let conf = ServerConf::from_cmd(global, args)?;
+    if dump_level > 0 {
+        return conf.dump();
+    }
+
+    let addr: SocketAddr = conf.listen.parse()?;
+    let state = Arc::new(ServerState {
+        client: super::api::Client::new(&conf.common.remote)?
+            .with_http(conf.common.http_options()?)?
+            .with_policy(conf._policy),
+        contexts: conf.contexts,
+        devices: conf.devices,
+        semaphore: Arc::new(Semaphore::new(conf.limit_conn as usize)),
+    });
+
+    Runtime::new()?.block_on(async {
+        let make_service = make_service_fn(move |_| {
+            let state = state.clone();
+            async move { Ok::<_, Infallible>(service_fn(move |req| state.clone().handle(req))) }
+        });
+
+        let server = hyper::Server::try_bind(&addr)
+            .with_context(|| format!("bind server_listen `{addr}` failed"))?
+            .serve(make_service);
+
+        super::cli::Output::exec(&format!(
+            "Server -L {} -R {} -l {}",
+            server.local_addr(),
+            super::bark::Remote::scheme_host_port(&conf.common.remote)?,
+            conf.limit_conn
+        ));
+
+        server
+            .with_graceful_shutdown(async {
+                tokio::signal::ctrl_c().await.ok();
+            })
+            .await?;
+
+        Ok(())
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::*;
+    use super::*;
+    use crate::util::tests::*;
+    use clap::Parser;
+
+    #[test]
+    fn test_dump() -> anyhow::Result<()> {
+        let cli = cli::Main::parse_from(["", "server", "-L", "127.0.0.1:0", "-DD"]);
+        match cli.command.unwrap() {
+            cmd::Commands::Server(args) => exec(cli.global, args)?,
+            _ => unreachable!(),
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_push_parse() -> anyhow::Result<()> {
+        let push = Push::from_json(
+            br#"{"device_key":"a","device_keys":["b","c"],"title":"iBark","badge":1,"sound":null}"#,
+        )?;
+        dbg!(&push);
+        assert_eq!(push.devices, ["a", "b", "c"]);
+        assert_eq!(push.contexts.get("badge").unwrap(), "1");
+        assert!(!push.contexts.contains_key("sound"));
+
+        let push = Push::from_form(b"device_keys=a,b&title=iBark&body=Hello%20World");
+        dbg!(&push);
+        assert_eq!(push.devices, ["a", "b"]);
+        assert_eq!(push.contexts.get("body").unwrap(), "Hello World");
+
+        assert!(Push::from_json(br#"{"title":{"a":1}}"#).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_handle() -> anyhow::Result<()> {
+        let state = Arc::new(ServerState {
+            client: api::Client::new(&format!("https://{}.com", random_string(10)))?,
+            contexts: Default::default(),
+            devices: crate::hash_map! {
+                "phone".to_string() => bark::DeviceConf::Url(format!("d://{}", random_string(22)))
+            },
+            semaphore: Arc::new(Semaphore::new(1)),
+        });
+
+        Runtime::new()?.block_on(async {
+            let cases = [
+                (Method::GET, "/ping", "", StatusCode::OK),
+                (Method::GET, "/healthz", "", StatusCode::OK),
+                (Method::GET, "/unknown", "", StatusCode::NOT_FOUND),
+                (
+                    Method::POST,
+                    "/",
+                    r#"{"device_key":"phone"}"#,
+                    StatusCode::NOT_FOUND,
+                ),
+                (
+                    Method::POST,
+                    "/phone/t",
+                    r#"{"title":"t"}"#,
+                    StatusCode::NOT_FOUND,
+                ),
+                (
+                    Method::POST,
+                    "/push/phone",
+                    r#"{"title":"t"}"#,
+                    StatusCode::NOT_FOUND,
+                ),
+                (
+                    Method::POST,
+                    "/push",
+                    r#"{"title":"t"}"#,
+                    StatusCode::BAD_REQUEST,
+                ),
+                (
+                    Method::POST,
+                    "/push",
+                    r#"{"device_key":"unknown"}"#,
+                    StatusCode::BAD_REQUEST,
+                ),
+                (
+                    Method::POST,
+                    "/phone",
+                    r#"{"l":"unknown"}"#,
+                    StatusCode::BAD_REQUEST,
+                ),
+            ];
+
+            for (method, path, body, status) in cases.into_iter() {
+                let req = Request::builder()
+                    .method(method)
+                    .uri(path)
+                    .header(CONTENT_TYPE, "application/json")
+                    .body(Body::from(body))?;
+                let resp = state.clone().handle(req).await?;
+                let code = resp.status();
+                dbg!(
+                    path,
+                    String::from_utf8(hyper::body::to_bytes(resp).await?.to_vec())?
+                );
+                assert_eq!(code, status);
+            }
+            Ok(())
+        })
+    }
+
+    #[test]
+    fn test_handle_fan_out() -> anyhow::Result<()> {
+        Runtime::new()?.block_on(async {
+            // a bark-server that takes every push
+            let pushed = Arc::new(std::sync::Mutex::new(Vec::new()));
+            let remote = {
+                let pushed = pushed.clone();
+                let make_service = make_service_fn(move |_| {
+                    let pushed = pushed.clone();
+                    async move {
+                        Ok::<_, Infallible>(service_fn(move |req: Request<Body>| {
+                            pushed.lock().unwrap().push(req.uri().path().to_string());
+                            async {
+                                Ok::<_, Infallible>(Response::new(Body::from(
+                                    r#"{"code":200,"message":"success","timestamp":1}"#,
+                                )))
+                            }
+                        }))
+                    }
+                });
+                let server = hyper::Server::try_bind(&"127.0.0.1:0".parse()?)?.serve(make_service);
+                let remote = format!("http://{}", server.local_addr());
+                tokio::spawn(server);
+                remote
+            };
+
+            let (phone, pad) = (random_string(22), random_string(22));
+            let state = Arc::new(ServerState {
+                client: api::Client::new(&remote)?,
+                contexts: Default::default(),
+                devices: crate::hash_map! {
+                    "phone".to_string() => bark::DeviceConf::Url(format!("d://{phone}")),
+                    "pad".to_string() => bark::DeviceConf::Url(format!("d://{pad}"))
+                },
+                semaphore: Arc::new(Semaphore::new(2)),
+            });
+
+            for (path, body) in [
+                ("/push", r#"{"device_keys":["phone","pad"],"title":"t"}"#),
+                ("/phone", r#"{"device_key":"pad","title":"t"}"#),
+            ] {
+                let req = Request::builder()
+                    .method(Method::POST)
+                    .uri(path)
+                    .header(CONTENT_TYPE, "application/json")
+                    .body(Body::from(body))?;
+                let resp = state.clone().handle(req).await?;
+                assert_eq!(resp.status(), StatusCode::OK);
+            }
+
+            let mut pushed = pushed.lock().unwrap().clone();
+            pushed.sort();
+            let mut expected = [phone.clone(), pad.clone(), phone, pad].map(|v| format!("/{v}"));
+            expected.sort();
+            assert_eq!(pushed, expected);
+            Ok(())
+        })
+    }
+}
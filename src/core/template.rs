@@ -0,0 +1,117 @@
+use anyhow::{anyhow, Context};
+use std::collections::HashMap;
+
+pub struct Template;
+impl Template {
+    pub fn render(
+        templates: &HashMap<String, HashMap<String, String>>,
+        name: &str,
+        vars: &HashMap<String, String>,
+    ) -> anyhow::Result<HashMap<String, String>> {
+        let template = templates.get(name).ok_or_else(|| {
+            let mut names = templates.keys().map(|v| v.as_str()).collect::<Vec<_>>();
+            names.sort();
+            anyhow!("template `{name}` not match `{}`", names.join("|"))
+        })?;
+
+        let mut rendered = HashMap::with_capacity(template.len());
+        for (k, v) in template.iter() {
+            let v = Self::render_str(v, vars)
+                .with_context(|| format!("render template `{name}` context `{k}` failed"))?;
+            rendered.insert(k.to_string(), v);
+        }
+        Ok(rendered)
+    }
+
+    /// Replace every `{{name}}` in `s`, whitespace inside the braces is ignored.
+    pub fn render_str(s: &str, vars: &HashMap<String, String>) -> anyhow::Result<String> {
+        Self::render_with(s, |name| {
+            vars.get(name)
+                .cloned()
+                .ok_or_else(|| anyhow!("template_var `{name}` not provided, use --var {name}=..."))
+        })
+    }
+
+    /// Only the syntax, vars are given by each send.
+    pub fn verify_str(s: &str) -> anyhow::Result<()> {
+        Self::render_with(s, |_| Ok(String::new())).map(|_| ())
+    }
+
+    fn render_with(
+        s: &str,
+        mut var: impl FnMut(&str) -> anyhow::Result<String>,
+    ) -> anyhow::Result<String> {
+        let mut rendered = String::with_capacity(s.len());
+        let mut rest = s;
+
+        while let Some(start) = rest.find("{{") {
+            rendered.push_str(&rest[..start]);
+            rest = &rest[start + 2..];
+
+            let end = rest
+                .find("}}")
+                .ok_or_else(|| anyhow!("unclosed template_var `{{{{{rest}`"))?;
+            let name = rest[..end].trim();
+            if name.is_empty() {
+                return Err(anyhow!("empty template_var `{{{{}}}}`"));
+            }
+
+            rendered.push_str(&var(name)?);
+            rest = &rest[end + 2..];
+        }
+        rendered.push_str(rest);
+
+        Ok(rendered)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::util::tests::*;
+
+    #[test]
+    fn test_render_str() -> anyhow::Result<()> {
+        let service = random_string(10);
+        let vars = crate::hash_map! {
+            "service".to_string() => service.clone(),
+            "env".to_string() => "prod".to_string(),
+        };
+
+        assert_eq!(
+            Template::render_str("Deployed {{service}} to {{ env }}", &vars)?,
+            format!("Deployed {service} to prod")
+        );
+        assert_eq!(Template::render_str("no vars", &vars)?, "no vars");
+        assert_eq!(Template::render_str("{{env}}{{env}}", &vars)?, "prodprod");
+
+        for err in ["{{missing}}", "{{env", "{{ }}"] {
+            dbg!(Template::render_str(err, &vars).unwrap_err());
+        }
+
+        Template::verify_str("{{missing}} {{ env }}")?;
+        for err in ["{{env", "{{ }}"] {
+            dbg!(Template::verify_str(err).unwrap_err());
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_render() -> anyhow::Result<()> {
+        let templates = crate::hash_map! {
+            "deploy".to_string() => crate::hash_map! {
+                "title".to_string() => "Deployed {{service}}".to_string(),
+                "level".to_string() => "timeSensitive".to_string(),
+            }
+        };
+        let vars = crate::hash_map! { "service".to_string() => "api".to_string() };
+
+        let rendered = Template::render(&templates, "deploy", &vars)?;
+        dbg!(&rendered);
+        assert_eq!(rendered.get("title").unwrap(), "Deployed api");
+
+        dbg!(Template::render(&templates, "unknown", &vars).unwrap_err());
+        dbg!(Template::render(&templates, "deploy", &HashMap::new()).unwrap_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,35 @@
+//! Push notifications to [Bark](https://github.com/Finb/Bark), the library behind `ibark`.
+//!
+//! Devices take the same url form as the config, `d://<device_key>` or `aes://...`.
+//!
+//! ```no_run
+//! use ibark::{Client, Device, Level, Notification};
+//!
+//! # async fn run() -> anyhow::Result<()> {
+//! let client = Client::new("https://api.day.app")?;
+//! let device: Device = "d://<device_key>".parse()?;
+//! let notification = Notification::new()
+//!     .title("Deploy")
+//!     .body("done")
+//!     .level(Level::TimeSensitive);
+//!
+//! let response = client.send(&device, &notification).await?;
+//! if !response.is_success() {
+//!     eprintln!("{}", response.reason());
+//! }
+//! # Ok(())
+//! # }
+//! ```
+
+mod core;
+mod macros;
+mod util;
+
+pub use crate::core::api::{Client, Device, Level, Notification, RequestStyle, Response, Sound};
+
+/// The `ibark` command line.
+#[cfg(feature = "cli")]
+#[doc(hidden)]
+pub fn cli() -> anyhow::Result<()> {
+    crate::core::app::start()
+}
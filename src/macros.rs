@@ -77,9 +77,8 @@ macro_rules! println_dash {
     };
 }
 
-#[cfg(test)]
+#[cfg(all(test, feature = "cli"))]
 mod tests {
-    use super::*;
     use crate::util;
 
     #[test]
@@ -1,3 +1,4 @@
+#[cfg(feature = "cli")]
 pub fn hash_hex_string<T: std::hash::Hash>(item: T) -> String {
     use std::{collections::hash_map::DefaultHasher, hash::Hasher};
 
@@ -7,6 +8,37 @@ pub fn hash_hex_string<T: std::hash::Hash>(item: T) -> String {
     format!("{:X}", ret)
 }
 
+/// Truncate to at most `max` bytes without splitting a char, `…` marks the cut.
+pub fn truncate_utf8(s: &str, max: usize) -> std::borrow::Cow<'_, str> {
+    const MARK: &str = "…";
+    if s.len() <= max {
+        return s.into();
+    }
+
+    let mark = if max >= MARK.len() { MARK } else { "" };
+    let mut end = max - mark.len();
+    while !s.is_char_boundary(end) {
+        end -= 1;
+    }
+    format!("{}{mark}", &s[..end]).into()
+}
+
+/// Same as `truncate_utf8`, but keeps the end, e.g. the last lines of a log.
+#[cfg(feature = "cli")]
+pub fn truncate_utf8_start(s: &str, max: usize) -> std::borrow::Cow<'_, str> {
+    const MARK: &str = "…";
+    if s.len() <= max {
+        return s.into();
+    }
+
+    let mark = if max >= MARK.len() { MARK } else { "" };
+    let mut start = s.len() - (max - mark.len());
+    while !s.is_char_boundary(start) {
+        start += 1;
+    }
+    format!("{mark}{}", &s[start..]).into()
+}
+
 #[cfg(test)]
 pub mod tests {
     use super::*;
@@ -25,11 +57,36 @@ pub mod tests {
         rng.gen_range(min..=max)
     }
 
+    #[cfg(feature = "cli")]
     #[test]
     fn test_hash_hex_string() {
         dbg!(hash_hex_string(random_number(0, 100) as usize));
     }
 
+    #[test]
+    fn test_truncate_utf8() {
+        assert_eq!(truncate_utf8("hello", 5), "hello");
+        assert_eq!(truncate_utf8("hello world", 8), "hello…");
+        assert_eq!(truncate_utf8("👋🌍👋🌍", 10), "👋…");
+        assert_eq!(truncate_utf8("👋🌍", 2), "");
+
+        let s = random_string(random_number(0, 100) as usize) + "💗";
+        for max in 0..s.len() {
+            assert!(dbg!(truncate_utf8(&s, max)).len() <= max);
+        }
+    }
+
+    #[cfg(feature = "cli")]
+    #[test]
+    fn test_truncate_utf8_start() {
+        let s = random_string(random_number(0, 100) as usize) + "💗";
+        for max in 0..s.len() {
+            assert!(dbg!(truncate_utf8_start(&s, max)).len() <= max);
+        }
+        assert_eq!(truncate_utf8_start("hello world", 8), "…world");
+        assert_eq!(truncate_utf8_start("👋🌍👋🌍", 10), "…🌍");
+    }
+
     #[test]
     fn test_random() {
         dbg!(random_string(random_number(0, 100) as usize));